@@ -1,9 +1,15 @@
-use std::fmt::{Arguments, Display};
+use std::cell::Cell;
+use std::fmt::Display;
+use std::rc::Rc;
 
 use crate::token::{Literal, Token, TokenType};
 
 type WrappedExpr = Box<Expr>;
 
+/// How many scopes out from where it is used a variable was declared, as found by the resolver.
+/// `None` means the variable is global.
+pub(crate) type Depth = Cell<Option<usize>>;
+
 #[derive(Debug, Clone)]
 pub(crate) enum Expr {
     Literal {
@@ -11,10 +17,17 @@ pub(crate) enum Expr {
     },
     Variable {
         name: Token,
+        depth: Depth,
+    },
+    /// The instance a method is called on.
+    This {
+        keyword: Token,
+        depth: Depth,
     },
     Assign {
         name: Token,
         value: WrappedExpr,
+        depth: Depth,
     },
     Logical {
         left: WrappedExpr,
@@ -25,6 +38,12 @@ pub(crate) enum Expr {
         operator: Token,
         right: WrappedExpr,
     },
+    /// `condition ? then_branch : else_branch`, evaluating only the branch that is taken.
+    Ternary {
+        condition: WrappedExpr,
+        then_branch: WrappedExpr,
+        else_branch: WrappedExpr,
+    },
     Binary {
         left: WrappedExpr,
         operator: Token,
@@ -35,63 +54,377 @@ pub(crate) enum Expr {
         paren: Token,
         arguments: Vec<Expr>,
     },
+    /// Reading the property `name` of an instance.
+    Get {
+        object: WrappedExpr,
+        name: Token,
+    },
+    /// Writing the field `name` of an instance.
+    Set {
+        object: WrappedExpr,
+        name: Token,
+        value: WrappedExpr,
+    },
+    /// A list literal, like `[1, 2, 3]`.
+    List {
+        elements: Vec<Expr>,
+    },
+    /// A map literal, like `{"a": 1, "b": 2}`. The `brace` is the closing one, which is where
+    /// any errors are reported.
+    Map {
+        brace: Token,
+        entries: Vec<MapEntry>,
+    },
+    /// `...expression`, splicing the elements of a list into a list literal or the arguments of
+    /// a call, or the entries of a map into a map literal.
+    Spread {
+        ellipsis: Token,
+        expression: WrappedExpr,
+    },
+    /// Reading the element at `index` of a list, or the value at key `index` of a map. The
+    /// `bracket` is the closing one, which is where any errors are reported.
+    Index {
+        object: WrappedExpr,
+        bracket: Token,
+        index: WrappedExpr,
+    },
+    /// Writing the element at `index` of a list, or the value at key `index` of a map.
+    SetIndex {
+        object: WrappedExpr,
+        bracket: Token,
+        index: WrappedExpr,
+        value: WrappedExpr,
+    },
     Grouping {
         expression: WrappedExpr,
     },
+    /// Evaluates to the body of the first arm whose pattern equals the subject, or to the body of
+    /// the wildcard arm.
+    Match {
+        keyword: Token,
+        subject: WrappedExpr,
+        arms: Vec<MatchArm>,
+        wildcard: WrappedExpr,
+    },
+    /// Runs the body until a `break`, and evaluates to the value it breaks with.
+    Loop {
+        keyword: Token,
+        body: Vec<Stmt>,
+    },
+    /// Evaluates to the body of the first case whose value equals the subject, or to the default.
+    Switch {
+        keyword: Token,
+        subject: WrappedExpr,
+        cases: Vec<SwitchCase>,
+        default: Vec<Stmt>,
+    },
+}
+
+/// A `pattern => body` arm of a match.
+#[derive(Debug, Clone)]
+pub(crate) struct MatchArm {
+    pub(crate) pattern: Literal,
+    pub(crate) body: Expr,
+}
+
+/// An entry of a map literal.
+#[derive(Debug, Clone)]
+pub(crate) enum MapEntry {
+    /// `key: value`.
+    Pair(Expr, Expr),
+    /// `...map`, holding the `Expr::Spread`.
+    Spread(Expr),
+}
+
+/// A `case value: body` of a switch.
+#[derive(Debug, Clone)]
+pub(crate) struct SwitchCase {
+    pub(crate) value: Expr,
+    pub(crate) body: Vec<Stmt>,
+}
+
+/// How tightly an expression binds, from loosest to tightest, following the grammar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Assignment,
+    Conditional,
+    Or,
+    And,
+    Equality,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Comparison,
+    Shift,
+    Term,
+    Factor,
+    Unary,
+    Power,
+    Call,
+    Primary,
+}
+
+impl Expr {
+    fn precedence(&self) -> Precedence {
+        match self {
+            Expr::Assign { .. }
+            | Expr::Set { .. }
+            | Expr::SetIndex { .. }
+            | Expr::Spread { .. } => Precedence::Assignment,
+            Expr::Ternary { .. } => Precedence::Conditional,
+            Expr::Logical { operator, .. } | Expr::Binary { operator, .. } => {
+                match operator.token_type() {
+                    TokenType::Or => Precedence::Or,
+                    TokenType::And => Precedence::And,
+                    TokenType::BangEqual | TokenType::EqualEqual | TokenType::Is => {
+                        Precedence::Equality
+                    }
+                    TokenType::Pipe => Precedence::BitOr,
+                    TokenType::Caret => Precedence::BitXor,
+                    TokenType::Ampersand => Precedence::BitAnd,
+                    TokenType::Greater
+                    | TokenType::GreaterEqual
+                    | TokenType::Less
+                    | TokenType::LessEqual => Precedence::Comparison,
+                    TokenType::LessLess | TokenType::GreaterGreater => Precedence::Shift,
+                    TokenType::Minus | TokenType::Plus => Precedence::Term,
+                    TokenType::Slash | TokenType::Star | TokenType::Percent => Precedence::Factor,
+                    TokenType::StarStar => Precedence::Power,
+                    _ => unreachable!(),
+                }
+            }
+            Expr::Unary { .. } => Precedence::Unary,
+            Expr::Call { .. } | Expr::Get { .. } | Expr::Index { .. } => Precedence::Call,
+            // The parentheses of a grouping are put back in only where they are needed.
+            Expr::Grouping { expression } => expression.precedence(),
+            Expr::Literal { .. }
+            | Expr::Variable { .. }
+            | Expr::This { .. }
+            | Expr::List { .. }
+            | Expr::Map { .. }
+            | Expr::Match { .. }
+            | Expr::Loop { .. }
+            | Expr::Switch { .. } => Precedence::Primary,
+        }
+    }
+
+    /// Display this as the operand of an operator, which needs parentheses if it binds looser
+    /// than `min`, or as loose as `min` when `strict`.
+    fn operand(&self, min: Precedence, strict: bool) -> String {
+        let precedence = self.precedence();
+        if precedence < min || strict && precedence == min {
+            format!("({self})")
+        } else {
+            self.to_string()
+        }
+    }
 }
 
 impl Display for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let precedence = self.precedence();
         match self {
             Expr::Literal { value } => write!(f, "{value}"),
-            Expr::Variable { name } => write!(f, "{name}"),
-            Expr::Assign { name, value } => write!(f, "{name} = {value}"),
+            Expr::Variable { name, .. } => write!(f, "{name}"),
+            Expr::This { .. } => write!(f, "this"),
+            Expr::Assign { name, value, .. } => {
+                write!(f, "{name} = {}", value.operand(precedence, false))
+            }
+            // All binary operators are left-associative, except for `**`.
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } if operator.token_type() == TokenType::StarStar => write!(
+                f,
+                "{} ** {}",
+                left.operand(precedence, true),
+                right.operand(Precedence::Unary, false)
+            ),
             Expr::Logical {
                 left,
                 operator,
                 right,
-            } => {
-                let op = match operator.token_type() {
-                    TokenType::Or => "or",
-                    TokenType::And => "and",
-                    _ => unreachable!(),
-                };
-                write!(f, "{left} {op} {right}")
             }
-            Expr::Unary { operator, right } => write!(f, "({} {right})", operator.lexeme()),
-            Expr::Binary {
+            | Expr::Binary {
                 left,
                 operator,
                 right,
-            } => write!(f, "({left} {} {right})", operator.lexeme()),
+            } => write!(
+                f,
+                "{} {} {}",
+                left.operand(precedence, false),
+                operator.lexeme(),
+                right.operand(precedence, true)
+            ),
+            Expr::Unary { operator, right } => {
+                write!(
+                    f,
+                    "{}{}",
+                    operator.lexeme(),
+                    right.operand(precedence, false)
+                )
+            }
+            Expr::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+            } => write!(
+                f,
+                "{} ? {then_branch} : {}",
+                condition.operand(Precedence::Or, false),
+                else_branch.operand(precedence, false)
+            ),
             Expr::Call {
                 callee, arguments, ..
             } => {
-                let mut arguments: String = arguments.iter().map(|a| format!("{a}, ")).collect();
-                // TODO: This string manipulation is inellegant. intersperse would work nicely but
-                // it is unstable.
-                arguments.truncate(arguments.len() - 2);
+                let callee = callee.operand(precedence, false);
+                let arguments = arguments
+                    .iter()
+                    .map(|a| a.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
                 write!(f, "{callee}({arguments})")
             }
+            Expr::Get { object, name } => {
+                write!(f, "{}.{}", object.operand(precedence, false), name.lexeme())
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => write!(
+                f,
+                "{}.{} = {}",
+                object.operand(Precedence::Call, false),
+                name.lexeme(),
+                value.operand(precedence, false)
+            ),
+            Expr::List { elements } => {
+                let elements = elements
+                    .iter()
+                    .map(|element| element.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "[{elements}]")
+            }
+            Expr::Map { entries, .. } => {
+                let entries = entries
+                    .iter()
+                    .map(|entry| match entry {
+                        MapEntry::Pair(key, value) => format!("{key}: {value}"),
+                        MapEntry::Spread(spread) => spread.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "{{{entries}}}")
+            }
+            Expr::Spread { expression, .. } => write!(f, "...{expression}"),
+            Expr::Index { object, index, .. } => {
+                write!(f, "{}[{index}]", object.operand(precedence, false))
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => write!(
+                f,
+                "{}[{index}] = {}",
+                object.operand(Precedence::Call, false),
+                value.operand(precedence, false)
+            ),
             Expr::Grouping { expression } => write!(f, "{expression}"),
+            Expr::Match {
+                subject,
+                arms,
+                wildcard,
+                ..
+            } => {
+                write!(f, "match {subject} {{ ")?;
+                for arm in arms {
+                    write!(f, "{} => {}, ", arm.pattern, arm.body)?;
+                }
+                write!(f, "_ => {wildcard} }}")
+            }
+            Expr::Loop { body, .. } => write!(
+                f,
+                "loop {{ {} }}",
+                body.iter()
+                    .map(|stmt| stmt.to_string())
+                    .collect::<Vec<_>>()
+                    .join("  ")
+            ),
+            Expr::Switch {
+                subject,
+                cases,
+                default,
+                ..
+            } => {
+                let body = |statements: &[Stmt]| {
+                    statements
+                        .iter()
+                        .map(|stmt| stmt.to_string())
+                        .collect::<Vec<_>>()
+                        .join("  ")
+                };
+                write!(f, "switch {subject} {{ ")?;
+                for case in cases {
+                    write!(f, "case {}: {}  ", case.value, body(&case.body))?;
+                }
+                write!(f, "default: {} }}", body(default))
+            }
         }
     }
 }
 
-type WrappedStmt = Box<Stmt>;
+pub(crate) type WrappedStmt = Box<Stmt>;
 
 #[derive(Debug, Clone)]
 pub(crate) enum Stmt {
+    Assert {
+        keyword: Token,
+        condition: Expr,
+        message: Option<Expr>,
+        /// The source text of the condition, used when no message is given.
+        source: String,
+    },
     Block {
         statements: Vec<Stmt>,
     },
+    Break {
+        keyword: Token,
+        /// What the loop evaluates to when broken out of.
+        value: Option<Expr>,
+    },
     Expression {
         expression: Expr,
     },
+    Class {
+        name: Token,
+        /// The class inherited from, as an `Expr::Variable`.
+        superclass: Option<Expr>,
+        /// The methods, as `Stmt::Function` declarations.
+        methods: Vec<Stmt>,
+        /// The static fields, as `Stmt::Var` declarations.
+        statics: Vec<Stmt>,
+    },
+    /// A class whose static fields are its variants, each a distinct instance of it.
+    Enum {
+        name: Token,
+        variants: Vec<Token>,
+    },
+    Continue {
+        keyword: Token,
+    },
+    /// Continues with the body of the next case of the enclosing switch.
+    Fallthrough {
+        keyword: Token,
+    },
     Function {
         name: Token,
         params: Vec<Token>,
-        body: Vec<Stmt>,
+        body: Rc<[Stmt]>,
     },
     If {
         condition: Expr,
@@ -101,6 +434,10 @@ pub(crate) enum Stmt {
     Print {
         expression: Expr,
     },
+    /// Like `Print`, but writes to standard error.
+    EPrint {
+        expression: Expr,
+    },
     Return {
         keyword: Token,
         value: Option<Expr>,
@@ -112,12 +449,33 @@ pub(crate) enum Stmt {
     While {
         condition: Expr,
         body: WrappedStmt,
+        /// Evaluated after every run of the body, even one cut short by `continue`.
+        increment: Option<Expr>,
+        /// Runs when the loop finishes without a `break`.
+        else_branch: Option<WrappedStmt>,
+    },
+    /// Runs the body with `name` bound to a resource, which is closed however the body is left.
+    With {
+        keyword: Token,
+        name: Token,
+        initializer: Expr,
+        body: Vec<Stmt>,
     },
 }
 
 impl Display for Stmt {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Stmt::Assert {
+                condition,
+                message: Some(message),
+                ..
+            } => write!(f, "assert {condition}, {message}"),
+            Stmt::Assert {
+                condition,
+                message: None,
+                ..
+            } => write!(f, "assert {condition}"),
             Stmt::Block { statements } => write!(
                 f,
                 "{{ {} }}",
@@ -127,7 +485,18 @@ impl Display for Stmt {
                     .collect::<Vec<_>>()
                     .join("  ")
             ),
-            Stmt::Function { name, params, body } => write!(f, "<fn {name}>", name = name.lexeme()),
+            Stmt::Break { value, .. } => {
+                if let Some(value) = value {
+                    write!(f, "break {value}")
+                } else {
+                    write!(f, "break")
+                }
+            }
+            Stmt::Class { name, .. } => write!(f, "<class {name}>", name = name.lexeme()),
+            Stmt::Enum { name, .. } => write!(f, "<enum {name}>", name = name.lexeme()),
+            Stmt::Continue { .. } => write!(f, "continue"),
+            Stmt::Fallthrough { .. } => write!(f, "fallthrough"),
+            Stmt::Function { name, .. } => write!(f, "<fn {name}>", name = name.lexeme()),
             Stmt::Expression { expression } => write!(f, "{expression}"),
             Stmt::If {
                 condition,
@@ -141,6 +510,7 @@ impl Display for Stmt {
                 Ok(())
             }
             Stmt::Print { expression } => write!(f, "print {expression}"),
+            Stmt::EPrint { expression } => write!(f, "eprint {expression}"),
             Stmt::Return { value, .. } => {
                 if let Some(value) = value {
                     write!(f, "return {value}")
@@ -156,7 +526,35 @@ impl Display for Stmt {
                 name,
                 initializer: None,
             } => write!(f, "var {name}"),
-            Stmt::While { condition, body } => write!(f, "while ({condition}) {body}"),
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                else_branch,
+            } => {
+                if let Some(increment) = increment {
+                    write!(f, "while ({condition}) {{ {body}  {increment} }}")?;
+                } else {
+                    write!(f, "while ({condition}) {body}")?;
+                }
+                if let Some(else_branch) = else_branch {
+                    write!(f, " else {else_branch}")?;
+                };
+                Ok(())
+            }
+            Stmt::With {
+                name,
+                initializer,
+                body,
+                ..
+            } => write!(
+                f,
+                "with (var {name} = {initializer}) {{ {} }}",
+                body.iter()
+                    .map(|stmt| stmt.to_string())
+                    .collect::<Vec<_>>()
+                    .join("  ")
+            ),
         }
     }
 }
@@ -1,68 +1,320 @@
-use crate::ast::{Expr, Stmt};
-use crate::environment::{self, Environment};
+use std::fmt::Debug;
+use std::rc::Rc;
+
+use crate::ast::Stmt;
+use crate::environment::Environment;
 use crate::interpreter::Interpreter;
 use crate::token::{Literal, Token};
 use crate::LoxError;
 
 pub(crate) trait Callable {
-    fn new(declaration: Stmt) -> Option<Self>
-    where
-        Self: Sized;
+    /// Call with the given arguments. The `paren` is the closing parenthesis of the call
+    /// expression, which is where any errors are reported.
     fn call(
         &self,
         interpreter: &mut Interpreter,
         environment: &Environment,
+        paren: &Token,
         arguments: Vec<Literal>,
     ) -> Result<Literal, LoxError>;
     fn arity(&self) -> usize;
+
+    /// The least number of arguments this can be called with.
+    fn min_arity(&self) -> usize {
+        self.arity()
+    }
+
+    /// The greatest number of arguments this can be called with, which is `usize::MAX` if it
+    /// takes any number of arguments.
+    fn max_arity(&self) -> usize {
+        self.arity()
+    }
+
+    /// Describe the acceptable number of arguments, for use in error messages.
+    fn describe_arity(&self) -> String {
+        match (self.min_arity(), self.max_arity()) {
+            (min, max) if min == max => format!("{min}"),
+            (min, usize::MAX) => format!("at least {min}"),
+            (min, max) => format!("{min} to {max}"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Function {
     name: Token,
     params: Vec<String>,
-    body: Vec<Stmt>,
+    /// The body is shared between all calls, and with the declaration it came from.
+    body: Rc<[Stmt]>,
+    /// For a method, the instance it is bound to as `this`.
+    this: Option<Literal>,
+    /// Whether this is the `init` method of a class, which always returns its instance.
+    is_initializer: bool,
+    /// The environment the function was declared in, which its body can refer to.
+    closure: Environment,
 }
 
 impl Function {
-    pub(crate) fn name(&self) -> &Token {
-        &self.name
-    }
-}
-
-impl Callable for Function {
-    fn new(declaration: Stmt) -> Option<Self> {
+    pub(crate) fn new(declaration: &Stmt, closure: &Environment) -> Option<Self> {
         match declaration {
             Stmt::Function { name, params, body } => {
                 let params = params
                     .iter()
                     .map(|param| param.lexeme().to_string())
                     .collect();
-                Some(Self { name, params, body })
+                Some(Self {
+                    name: name.clone(),
+                    params,
+                    body: Rc::clone(body),
+                    this: None,
+                    is_initializer: false,
+                    closure: closure.clone(),
+                })
             }
             _ => None,
         }
     }
 
+    /// Create a method from its declaration in a class.
+    pub(crate) fn method(declaration: &Stmt, closure: &Environment) -> Option<Self> {
+        let method = Self::new(declaration, closure)?;
+        Some(Self {
+            is_initializer: method.name.lexeme() == "init",
+            ..method
+        })
+    }
+
+    /// Bind this method to `instance`, which it can then refer to as `this`.
+    pub(crate) fn bind(&self, instance: Literal) -> Self {
+        Self {
+            this: Some(instance),
+            ..self.clone()
+        }
+    }
+
+    pub(crate) fn name(&self) -> &Token {
+        &self.name
+    }
+}
+
+impl Callable for Function {
     fn call(
         &self,
         interpreter: &mut Interpreter,
-        environment: &Environment,
+        _environment: &Environment,
+        _paren: &Token,
         arguments: Vec<Literal>,
     ) -> Result<Literal, LoxError> {
-        let mut environment = Environment::from_parent(environment);
+        // The body sees the variables from where the function was declared, not those of the
+        // caller.
+        let environment = Environment::from_parent(&self.closure);
+        if let Some(this) = &self.this {
+            environment.define("this".to_string(), this.clone());
+        }
 
         for (n, param) in self.params.iter().enumerate() {
             // TODO: Is this unwrap guaranteed by invariants from parsing process?
             environment.define(param.to_string(), arguments.get(n).unwrap().clone());
         }
 
-        interpreter.execute_block(self.body.clone(), &mut environment)?;
+        // Without a return statement, a function returns the value of its trailing expression.
+        let value = match interpreter.execute_block(&self.body, &environment) {
+            // A return statement unwinds up to here, having set its value aside.
+            Err(e) if e.is_return_unwind() => {
+                interpreter.set_returned_explicitly(true);
+                interpreter.take_return_value().unwrap_or(Literal::Nil)
+            }
+            result => {
+                interpreter.set_returned_explicitly(false);
+                result?
+            }
+        };
 
-        Ok(Literal::Nil)
+        match (&self.this, self.is_initializer) {
+            // An initializer returns its instance, also when it returns early.
+            (Some(this), true) => Ok(this.clone()),
+            _ => Ok(value),
+        }
     }
 
     fn arity(&self) -> usize {
         self.params.len()
     }
 }
+
+/// The Rust implementation behind a [`NativeFunction`], when it doesn't need to capture anything.
+pub(crate) type NativeFn =
+    fn(&mut Interpreter, &Environment, &Token, Vec<Literal>) -> Result<Literal, LoxError>;
+
+/// A [`NativeFn`], or a closure that captures state, like a list method bound to its list.
+type NativeBody =
+    Rc<dyn Fn(&mut Interpreter, &Environment, &Token, Vec<Literal>) -> Result<Literal, LoxError>>;
+
+/// A function registered from Rust with [`Interpreter::register_native`], which only gets to see
+/// the arguments.
+pub type HostFn = Box<dyn Fn(&mut Interpreter, &[Literal]) -> Result<Literal, LoxError>>;
+
+/// A function implemented in Rust, callable from Lox.
+#[derive(Clone)]
+pub struct NativeFunction {
+    name: String,
+    arity: usize,
+    /// How many arguments beyond `arity` may be left out.
+    optional: usize,
+    /// Whether any number of arguments beyond `arity` is accepted as well.
+    variadic: bool,
+    /// Shared, because natives are copied around like any other value.
+    function: NativeBody,
+}
+
+impl NativeFunction {
+    /// Create a native function that takes `arity` arguments. Besides a [`NativeFn`], `function`
+    /// may be a closure, which keeps whatever it captures for as long as the native exists.
+    pub(crate) fn new(
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, &Environment, &Token, Vec<Literal>) -> Result<Literal, LoxError>
+            + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            arity,
+            optional: 0,
+            variadic: false,
+            function: Rc::new(function),
+        }
+    }
+
+    /// Create a native function that takes `arity` arguments, of which the last `optional` may be
+    /// left out.
+    pub(crate) fn optional(
+        name: &str,
+        arity: usize,
+        optional: usize,
+        function: impl Fn(&mut Interpreter, &Environment, &Token, Vec<Literal>) -> Result<Literal, LoxError>
+            + 'static,
+    ) -> Self {
+        Self {
+            optional,
+            ..Self::new(name, arity, function)
+        }
+    }
+
+    /// Create a native function that takes at least `arity` arguments.
+    pub(crate) fn variadic(
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, &Environment, &Token, Vec<Literal>) -> Result<Literal, LoxError>
+            + 'static,
+    ) -> Self {
+        Self {
+            variadic: true,
+            ..Self::new(name, arity, function)
+        }
+    }
+
+    /// Create a native function from a [`HostFn`].
+    pub(crate) fn host(name: &str, arity: usize, function: HostFn) -> Self {
+        Self {
+            name: name.to_string(),
+            arity,
+            optional: 0,
+            variadic: false,
+            function: Rc::new(move |interpreter, _, _, arguments| {
+                function(interpreter, &arguments)
+            }),
+        }
+    }
+
+    /// Bind the first argument to `receiver`, which makes this a method of it.
+    pub(crate) fn bind(&self, receiver: Literal) -> Self {
+        let function = Rc::clone(&self.function);
+        Self {
+            name: self.name.clone(),
+            arity: self.arity - 1,
+            optional: self.optional,
+            variadic: self.variadic,
+            function: Rc::new(move |interpreter, environment, paren, mut arguments| {
+                arguments.insert(0, receiver.clone());
+                function(interpreter, environment, paren, arguments)
+            }),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .field("optional", &self.optional)
+            .field("variadic", &self.variadic)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Callable for NativeFunction {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        environment: &Environment,
+        paren: &Token,
+        arguments: Vec<Literal>,
+    ) -> Result<Literal, LoxError> {
+        (self.function)(interpreter, environment, paren, arguments)
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn min_arity(&self) -> usize {
+        self.arity - self.optional
+    }
+
+    fn max_arity(&self) -> usize {
+        if self.variadic {
+            usize::MAX
+        } else {
+            self.arity
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use crate::scanner::Scanner;
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        let tokens = Scanner::new(source, 1).scan_tokens().unwrap();
+        let statements = Parser::new(tokens, 1).parse().unwrap();
+        Resolver::new(1).resolve(&statements).unwrap();
+        statements
+    }
+
+    #[test]
+    fn calls_share_the_declared_body() {
+        let declaration = parse("fun countdown(n) { if (n > 0) countdown(n - 1); }");
+        let Stmt::Function { body, .. } = &declaration[0] else {
+            panic!("expected a function declaration");
+        };
+        let mut interpreter = Interpreter::new();
+        interpreter.interpret(declaration.clone()).unwrap();
+
+        let returned = interpreter.interpret(parse("countdown(10); countdown;"));
+        let Ok(Some(Literal::Fun(function))) = returned else {
+            panic!("expected the function, got {returned:?}");
+        };
+        assert!(Rc::ptr_eq(&function.body, body));
+        // The declaration, the function defined in the globals and the copy returned here. None of
+        // the calls left a copy of the body behind.
+        assert_eq!(Rc::strong_count(body), 3);
+    }
+}
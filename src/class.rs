@@ -0,0 +1,145 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::callable::{Callable, Function};
+use crate::environment::Environment;
+use crate::interpreter::{Allocation, Interpreter};
+use crate::token::{Literal, Token};
+use crate::LoxError;
+
+#[derive(Debug)]
+pub struct Class {
+    name: Token,
+    /// The class that methods are looked up in when this class doesn't have them.
+    superclass: Option<Rc<Class>>,
+    methods: HashMap<String, Function>,
+    /// The constants declared with `static`, which are read from the class itself.
+    statics: RefCell<HashMap<String, Literal>>,
+}
+
+impl Class {
+    pub(crate) fn new(
+        name: Token,
+        superclass: Option<Rc<Class>>,
+        methods: HashMap<String, Function>,
+        statics: HashMap<String, Literal>,
+    ) -> Self {
+        Self {
+            name,
+            superclass,
+            methods,
+            statics: RefCell::new(statics),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &Token {
+        &self.name
+    }
+
+    /// Find the method `name` in this class, or else in the classes it inherits from.
+    pub(crate) fn find_method(&self, name: &str) -> Option<&Function> {
+        self.methods.get(name).or_else(|| {
+            self.superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name))
+        })
+    }
+
+    /// Find the static field `name` in this class, or else in the classes it inherits from.
+    pub(crate) fn find_static(&self, name: &str) -> Option<Literal> {
+        self.statics.borrow().get(name).cloned().or_else(|| {
+            self.superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_static(name))
+        })
+    }
+
+    /// Define the static field `name` after the class is created, for values that are instances
+    /// of the class itself, like the variants of an enum.
+    pub(crate) fn define_static(&self, name: String, value: Literal) {
+        self.statics.borrow_mut().insert(name, value);
+    }
+}
+
+/// Calling a class constructs a new instance of it, which is set up by its `init` method with
+/// the arguments of the call.
+impl Callable for Rc<Class> {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        environment: &Environment,
+        paren: &Token,
+        arguments: Vec<Literal>,
+    ) -> Result<Literal, LoxError> {
+        let instance = Instance::new(Rc::clone(self));
+        interpreter.count_allocation(Allocation::Instance);
+        let instance = Literal::Instance(Rc::new(RefCell::new(instance)));
+        match self.find_method("init") {
+            Some(initializer) => {
+                initializer
+                    .bind(instance)
+                    .call(interpreter, environment, paren, arguments)
+            }
+            None => Ok(instance),
+        }
+    }
+
+    fn arity(&self) -> usize {
+        self.find_method("init")
+            .map_or(0, |initializer| initializer.arity())
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    class: Rc<Class>,
+    fields: HashMap<String, Literal>,
+}
+
+impl Instance {
+    pub(crate) fn new(class: Rc<Class>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn class(&self) -> &Class {
+        &self.class
+    }
+
+    pub(crate) fn fields(&self) -> &HashMap<String, Literal> {
+        &self.fields
+    }
+
+    /// Get the value of the property `name` of `instance`: the field by that name, or else the
+    /// method, bound to the instance. Fields shadow methods.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the instance has no such property.
+    pub(crate) fn get(instance: &Rc<RefCell<Self>>, name: &Token) -> Result<Literal, LoxError> {
+        Self::property(instance, name.lexeme()).ok_or_else(|| {
+            LoxError::from_token(name, format!("Undefined property '{}'.", name.lexeme()))
+        })
+    }
+
+    /// Like [`Instance::get`], but for a property name that is only known at runtime, as in
+    /// `instance["name"]`.
+    pub(crate) fn property(instance: &Rc<RefCell<Self>>, name: &str) -> Option<Literal> {
+        let this = instance.borrow();
+        if let Some(field) = this.fields.get(name) {
+            return Some(field.clone());
+        }
+        this.class.find_method(name).map(|method| {
+            let method = method.bind(Literal::Instance(Rc::clone(instance)));
+            Literal::Fun(Box::new(method))
+        })
+    }
+
+    /// Set the field `name`, defining it if it doesn't exist yet.
+    pub(crate) fn set(&mut self, name: &str, value: Literal) {
+        self.fields.insert(name.to_string(), value);
+    }
+}
@@ -1,39 +1,64 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt::Debug;
+use std::rc::Rc;
 
 use crate::token::{Literal, Token};
 use crate::LoxError;
 
 type Object = Literal;
 
-#[derive(Debug, Clone)]
+/// A scope of variables. Cloning an environment gives another handle to the same scope, such
+/// that a closure and the code around it see each other's assignments.
+#[derive(Clone, Default)]
 pub(crate) struct Environment {
-    fallback: Option<Box<Self>>,
+    scope: Rc<RefCell<Scope>>,
+}
+
+#[derive(Default)]
+struct Scope {
+    fallback: Option<Environment>,
     values: HashMap<String, Object>,
+    /// Whether assigning to an undefined variable defines it, rather than being an error. Only
+    /// has an effect on the outermost environment.
+    implicit_globals: bool,
 }
 
 impl Environment {
     pub(crate) fn new() -> Self {
-        Self {
-            fallback: None,
-            values: HashMap::new(),
-        }
+        Self::default()
     }
 
     pub(crate) fn from_parent(environment: &Environment) -> Self {
         Self {
-            fallback: Some(Box::new(environment.clone())),
-            values: HashMap::new(),
+            scope: Rc::new(RefCell::new(Scope {
+                fallback: Some(environment.clone()),
+                ..Scope::default()
+            })),
         }
     }
 
-    pub(crate) fn fallback(self) -> Option<Environment> {
-        self.fallback.map(|env| *env)
+    pub(crate) fn set_implicit_globals(&self, implicit_globals: bool) {
+        self.scope.borrow_mut().implicit_globals = implicit_globals;
+    }
+}
+
+// A function holds on to the environment it was defined in, which in turn holds the function, so
+// printing the whole chain would never end.
+impl Debug for Environment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scope = self.scope.borrow();
+        let mut names = scope.values.keys().collect::<Vec<_>>();
+        names.sort();
+        f.debug_struct("Environment")
+            .field("names", &names)
+            .finish_non_exhaustive()
     }
 }
 
 impl Environment {
-    pub(crate) fn define(&mut self, name: String, value: Object) {
-        self.values.insert(name, value);
+    pub(crate) fn define(&self, name: String, value: Object) {
+        self.scope.borrow_mut().values.insert(name, value);
     }
 
     /// Get the Literal value bound to a variable.
@@ -41,44 +66,111 @@ impl Environment {
     /// # Errors
     ///
     /// This function will return an error if the variable is not found.
-    pub(crate) fn get_var(&self, name: &Token) -> Result<&Object, LoxError> {
-        let lexeme = name.lexeme().to_owned();
-        match self.fallback {
-            // If there is no enclosing `fallback` environment, get the variable name from this
-            // environment.
-            None => self.values.get(&lexeme),
-            // Otherwise, try to get it from this environment, but when it is not present, get it
-            // from the enclosing environment.
-            Some(ref fallback) => match self.values.get(&lexeme) {
-                None => return fallback.get_var(name),
-                value => value,
+    pub(crate) fn get_var(&self, name: &Token) -> Result<Object, LoxError> {
+        let lexeme = name.lexeme();
+        let scope = self.scope.borrow();
+        match scope.values.get(lexeme) {
+            Some(value) => Ok(value.clone()),
+            // When it is not present in this environment, get it from the enclosing one.
+            None => match scope.fallback {
+                Some(ref fallback) => fallback.get_var(name),
+                None => Err(LoxError::from_token(
+                    name,
+                    format!("Undefined variable '{lexeme}'."),
+                )),
             },
         }
-        .ok_or_else(|| LoxError::from_token(name, format!("Undefined variable '{lexeme}'.")))
+    }
+
+    /// Get the environment `distance` scopes out from this one.
+    fn ancestor(&self, distance: usize) -> Environment {
+        let mut environment = self.clone();
+        for _ in 0..distance {
+            let fallback = environment.scope.borrow().fallback.clone();
+            environment = fallback.expect("the resolver only counts scopes that exist");
+        }
+        environment
+    }
+
+    /// Get the value of a variable from the scope `distance` out, where the resolver found its
+    /// declaration.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the variable has not been defined there yet.
+    pub(crate) fn get_at(&self, distance: usize, name: &Token) -> Result<Object, LoxError> {
+        let lexeme = name.lexeme();
+        self.ancestor(distance)
+            .scope
+            .borrow()
+            .values
+            .get(lexeme)
+            .cloned()
+            .ok_or_else(|| LoxError::from_token(name, format!("Undefined variable '{lexeme}'.")))
+    }
+
+    /// Assign to a variable in the scope `distance` out, where the resolver found its
+    /// declaration.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the variable has not been defined there yet.
+    pub(crate) fn assign_at(
+        &self,
+        distance: usize,
+        name: &Token,
+        value: Literal,
+    ) -> Result<Literal, LoxError> {
+        let lexeme = name.lexeme();
+        match self
+            .ancestor(distance)
+            .scope
+            .borrow_mut()
+            .values
+            .get_mut(lexeme)
+        {
+            Some(slot) => {
+                *slot = value.clone();
+                Ok(value)
+            }
+            None => Err(LoxError::from_token(
+                name,
+                format!("Undefined variable '{lexeme}'."),
+            )),
+        }
     }
 
     /// Assign another Literal value to a variable.
     ///
+    /// If the variable is not found and this is the outermost environment with implicit globals
+    /// enabled, the variable is defined here instead.
+    ///
     /// # Errors
     ///
     /// This function will return an error if the variable is not found.
-    pub(crate) fn assign(&mut self, name: Token, value: Literal) -> Result<Literal, LoxError> {
+    pub(crate) fn assign(&self, name: &Token, value: Literal) -> Result<Literal, LoxError> {
         let lexeme = name.lexeme().to_owned();
-        if self.values.contains_key(&lexeme) {
+        let mut scope = self.scope.borrow_mut();
+        if let Some(slot) = scope.values.get_mut(&lexeme) {
             // The variable exists in the current scope. Nice. We assign the value to this
             // variable and return the value.
-            self.values.insert(lexeme, value.clone());
+            *slot = value.clone();
             return Ok(value);
         }
 
         // The variable does not exist in the current scope. Let's try whether it is in the
         // previous scope.
-        if let Some(ref mut fallback) = self.fallback {
+        if let Some(ref fallback) = scope.fallback {
             return fallback.assign(name, value);
         }
 
+        if scope.implicit_globals {
+            scope.values.insert(lexeme, value.clone());
+            return Ok(value);
+        }
+
         Err(LoxError::from_token(
-            &name,
+            name,
             format!("Undefined variable '{lexeme}'."),
         ))
     }
@@ -1,41 +1,383 @@
-use crate::ast::{Expr, Stmt};
-use crate::callable::{Callable, Function};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::io::{stdin, BufRead};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::ast::{Depth, Expr, MapEntry, Stmt};
+use crate::callable::{Function, HostFn, NativeFunction};
+use crate::class::{Class, Instance};
 use crate::environment::Environment;
-use crate::token::{Literal, TokenType};
-use crate::LoxError;
+use crate::natives;
+use crate::token::{Literal, Number, Token, TokenType};
+use crate::{LoxError, VERSION};
 
 #[derive(Debug, Clone)]
 pub(crate) struct Interpreter {
-    globals: Box<Environment>,
-    backtrace: Vec<Expr>,
+    /// The outermost environment, holding the native functions and anything defined at the top
+    /// level of a program.
+    globals: Environment,
     return_value: Option<Literal>,
-    // environment: Environment,
+    /// Whether the last call to a Lox function ended in a return statement, rather than at the
+    /// end of its body.
+    returned_explicitly: bool,
+    /// Whether calls to Lox functions, and what they return, are traced to stderr.
+    tracing: bool,
+    /// The value that the innermost loop expression is being broken out of with.
+    break_value: Option<Literal>,
+    /// When sandboxed, natives that reach outside of the interpreter, like `getenv`, refuse to
+    /// run.
+    sandboxed: bool,
+    /// The flags that `#if` directives in the source check for.
+    flags: HashSet<String>,
+    /// The buffers that output is being captured in instead of written to its sink, innermost
+    /// last.
+    captures: Vec<(Sink, String)>,
+    /// The deadlines of the `withTimeout` calls that are running, innermost last.
+    deadlines: Vec<Deadline>,
+    /// What natives like `input` read from instead of stdin, if anything.
+    input: Option<Input>,
+    /// Whether lists are copied when they are assigned or passed, rather than shared.
+    value_lists: bool,
+    /// When set, the values that have been allocated so far are counted in here.
+    alloc_stats: Option<AllocStats>,
+}
+
+/// The kinds of values whose allocations are counted in [`AllocStats`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Allocation {
+    List,
+    Map,
+    Instance,
+    Function,
+}
+
+/// How many values of each kind have been allocated.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AllocStats {
+    lists: usize,
+    maps: usize,
+    instances: usize,
+    functions: usize,
+}
+
+impl Display for AllocStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Allocated lists: {}, maps: {}, instances: {}, functions: {}",
+            self.lists, self.maps, self.instances, self.functions
+        )
+    }
+}
+
+/// A reader that stands in for stdin.
+#[derive(Clone)]
+struct Input(Rc<RefCell<dyn BufRead>>);
+
+impl std::fmt::Debug for Input {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Input").finish_non_exhaustive()
+    }
+}
+
+/// Where output that isn't captured is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Sink {
+    Stdout,
+    Stderr,
+}
+
+/// A point in time after which running code is aborted, checked at every call and loop iteration.
+#[derive(Debug, Clone)]
+struct Deadline {
+    at: Instant,
+    /// Where the deadline was set, which is where a timeout is reported.
+    paren: Token,
+    millis: Number,
 }
 
 impl Interpreter {
     pub(crate) fn new() -> Self {
-        Self {
-            globals: Box::new(Environment::new()), // environment: Environment::new(),
-            backtrace: Vec::new(),
+        let mut interpreter = Self {
+            globals: Environment::new(),
             return_value: None,
+            returned_explicitly: false,
+            tracing: false,
+            break_value: None,
+            sandboxed: false,
+            flags: HashSet::new(),
+            captures: Vec::new(),
+            deadlines: Vec::new(),
+            input: None,
+            value_lists: false,
+            alloc_stats: None,
+        };
+        natives::define_natives(&mut interpreter);
+        interpreter.globals.define(
+            "__version__".to_string(),
+            Literal::String(VERSION.to_string()),
+        );
+        interpreter
+    }
+
+    pub(crate) fn set_sandboxed(&mut self, sandboxed: bool) {
+        self.sandboxed = sandboxed;
+    }
+
+    pub(crate) fn sandboxed(&self) -> bool {
+        self.sandboxed
+    }
+
+    /// Take the value of the return statement that is being unwound from.
+    pub(crate) fn take_return_value(&mut self) -> Option<Literal> {
+        self.return_value.take()
+    }
+
+    pub(crate) fn set_tracing(&mut self, tracing: bool) {
+        self.tracing = tracing;
+    }
+
+    /// Record whether the Lox function that was just called ended in a return statement. Only Lox
+    /// functions record this, or calling `returnedExplicitly()` would change its own answer.
+    pub(crate) fn set_returned_explicitly(&mut self, returned_explicitly: bool) {
+        self.returned_explicitly = returned_explicitly;
+    }
+
+    pub(crate) fn returned_explicitly(&self) -> bool {
+        self.returned_explicitly
+    }
+
+    /// Define a flag, such that source between `#if name` and `#endif` is included.
+    pub(crate) fn define_flag(&mut self, name: &str) {
+        self.flags.insert(name.to_string());
+    }
+
+    pub(crate) fn flags(&self) -> &HashSet<String> {
+        &self.flags
+    }
+
+    /// Write `output` to stdout, or to the innermost buffer if output is being captured.
+    pub(crate) fn write_output(&mut self, output: &str) {
+        self.write_to(Sink::Stdout, output);
+    }
+
+    /// Write `output` to the `sink`, or to the innermost buffer capturing that sink.
+    pub(crate) fn write_to(&mut self, sink: Sink, output: &str) {
+        let capture = self
+            .captures
+            .iter_mut()
+            .rev()
+            .find(|(captured, _)| *captured == sink);
+        match (capture, sink) {
+            (Some((_, capture)), _) => capture.push_str(output),
+            (None, Sink::Stdout) => print!("{output}"),
+            (None, Sink::Stderr) => eprint!("{output}"),
         }
     }
 
-    fn evaluate(&mut self, expr: Expr, environment: &mut Environment) -> Result<Literal, LoxError> {
+    /// Run `f`, capturing everything it writes to the `sink` rather than writing it there.
+    pub(crate) fn capture_output(
+        &mut self,
+        sink: Sink,
+        f: impl FnOnce(&mut Self) -> Result<Literal, LoxError>,
+    ) -> Result<String, LoxError> {
+        self.captures.push((sink, String::new()));
+        let result = f(self);
+        let output = self
+            .captures
+            .pop()
+            .map(|(_, output)| output)
+            .unwrap_or_default();
+        result.map(|_| output)
+    }
+
+    /// Read input from `input` rather than from stdin.
+    #[cfg(test)]
+    pub(crate) fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.input = Some(Input(Rc::new(RefCell::new(input))));
+    }
+
+    /// Run `f` on the reader that input is read from, which is stdin unless another was set.
+    pub(crate) fn with_input<T>(&mut self, f: impl FnOnce(&mut dyn BufRead) -> T) -> T {
+        match &self.input {
+            Some(Input(input)) => f(&mut *input.borrow_mut()),
+            None => f(&mut stdin().lock()),
+        }
+    }
+
+    /// Run `f`, aborting it with an error reported at `paren` once `millis` milliseconds have
+    /// passed.
+    pub(crate) fn with_timeout(
+        &mut self,
+        millis: Number,
+        paren: &Token,
+        f: impl FnOnce(&mut Self) -> Result<Literal, LoxError>,
+    ) -> Result<Literal, LoxError> {
+        // A deadline too far away to represent is as good as none.
+        let Some(at) = Instant::now().checked_add(Duration::from_millis(millis as u64)) else {
+            return f(self);
+        };
+        self.deadlines.push(Deadline {
+            at,
+            paren: paren.clone(),
+            millis,
+        });
+        let result = f(self);
+        self.deadlines.pop();
+        result
+    }
+
+    /// Return an error if any of the running `withTimeout` calls has run out of time.
+    fn check_deadlines(&self) -> Result<(), LoxError> {
+        match self
+            .deadlines
+            .iter()
+            .find(|deadline| deadline.at <= Instant::now())
+        {
+            Some(deadline) => Err(LoxError::from_token(
+                &deadline.paren,
+                format!("Timed out after {} ms.", deadline.millis),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Allow assigning to undefined variables, which then become globals.
+    pub(crate) fn set_implicit_globals(&mut self, implicit_globals: bool) {
+        self.globals.set_implicit_globals(implicit_globals);
+    }
+
+    /// Copy lists when they are assigned or passed, such that changing a list never changes it
+    /// through another variable.
+    pub(crate) fn set_value_lists(&mut self, value_lists: bool) {
+        self.value_lists = value_lists;
+    }
+
+    /// Start or stop counting the values that are allocated.
+    pub(crate) fn set_alloc_stats(&mut self, alloc_stats: bool) {
+        self.alloc_stats = alloc_stats.then(AllocStats::default);
+    }
+
+    /// The counts of the values allocated since counting started, if it did.
+    pub(crate) fn alloc_stats(&self) -> Option<&AllocStats> {
+        self.alloc_stats.as_ref()
+    }
+
+    /// Count the allocation of a value, if allocations are being counted.
+    pub(crate) fn count_allocation(&mut self, allocation: Allocation) {
+        let Some(stats) = &mut self.alloc_stats else {
+            return;
+        };
+        let count = match allocation {
+            Allocation::List => &mut stats.lists,
+            Allocation::Map => &mut stats.maps,
+            Allocation::Instance => &mut stats.instances,
+            Allocation::Function => &mut stats.functions,
+        };
+        *count += 1;
+    }
+
+    /// Give the `value` that is being assigned or passed. With value semantics for lists, a list
+    /// is copied, along with any lists in it.
+    fn pass(&mut self, value: Literal) -> Literal {
+        match value {
+            Literal::List(elements) if self.value_lists => {
+                self.count_allocation(Allocation::List);
+                let elements = elements
+                    .borrow()
+                    .iter()
+                    .map(|element| self.pass(element.clone()))
+                    .collect();
+                Literal::List(Rc::new(RefCell::new(elements)))
+            }
+            value => value,
+        }
+    }
+
+    /// Define a native function in the global environment. Like any closure, `function` may
+    /// capture state that it keeps between calls.
+    pub(crate) fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, &Environment, &Token, Vec<Literal>) -> Result<Literal, LoxError>
+            + 'static,
+    ) {
+        let native = NativeFunction::new(name, arity, function);
+        self.globals
+            .define(name.to_string(), Literal::Native(native));
+    }
+
+    /// Register a function written in Rust in the global environment, such that Lox code can call
+    /// it by `name` with `arity` arguments.
+    pub fn register_native(&mut self, name: &str, arity: usize, function: HostFn) {
+        let native = NativeFunction::host(name, arity, function);
+        self.globals
+            .define(name.to_string(), Literal::Native(native));
+    }
+
+    /// Define a native function that takes `arity` arguments in the global environment, of which
+    /// the last `optional` may be left out.
+    pub(crate) fn define_optional_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        optional: usize,
+        function: impl Fn(&mut Interpreter, &Environment, &Token, Vec<Literal>) -> Result<Literal, LoxError>
+            + 'static,
+    ) {
+        let native = NativeFunction::optional(name, arity, optional, function);
+        self.globals
+            .define(name.to_string(), Literal::Native(native));
+    }
+
+    /// Define a native function that takes at least `arity` arguments in the global environment.
+    pub(crate) fn define_variadic_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, &Environment, &Token, Vec<Literal>) -> Result<Literal, LoxError>
+            + 'static,
+    ) {
+        let native = NativeFunction::variadic(name, arity, function);
+        self.globals
+            .define(name.to_string(), Literal::Native(native));
+    }
+
+    fn evaluate(&mut self, expr: &Expr, environment: &Environment) -> Result<Literal, LoxError> {
         match expr {
-            Expr::Literal { value } => Ok(value),
+            Expr::Literal { value } => Ok(value.clone()),
             // TODO: I don't know whether this is right but we'll see.
-            Expr::Variable { ref name } => environment.get_var(name).cloned(),
-            Expr::Assign { name, value } => {
-                let value = self.evaluate(*value, environment)?;
-                environment.assign(name, value)
+            Expr::Variable { name, depth } => self.look_up_variable(name, depth, environment),
+            // A method is called with the instance bound to `this`.
+            Expr::This { keyword, depth } => self.look_up_variable(keyword, depth, environment),
+            Expr::Assign { name, value, depth } => {
+                let value = self.evaluate(value, environment)?;
+                let value = self.pass(value);
+                match depth.get() {
+                    Some(depth) => environment.assign_at(depth, name, value),
+                    None => self.globals.assign(name, value),
+                }
+            }
+            Expr::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.evaluate(condition, environment)?.is_truthy() {
+                    self.evaluate(then_branch, environment)
+                } else {
+                    self.evaluate(else_branch, environment)
+                }
             }
             Expr::Logical {
                 left,
                 operator,
                 right,
             } => {
-                let left = self.evaluate(*left, environment)?;
+                let left = self.evaluate(left, environment)?;
 
                 // TODO: Try some different arrangements to see whether it makes a
                 // performance impact. I feel there is a really cool optimalisation
@@ -60,15 +402,15 @@ impl Interpreter {
                     _ => unreachable!(),
                 }
 
-                self.evaluate(*right, environment)
+                self.evaluate(right, environment)
             }
             Expr::Unary { operator, right } => {
-                let right = self.evaluate(*right, environment)?;
+                let right = self.evaluate(right, environment)?;
                 match operator.token_type() {
                     TokenType::Bang => Ok(right.operate_truthy(|n| !n)),
                     TokenType::Minus => right
                         .operate_number(|n| -n)
-                        .ok_or(LoxError::unexpected_type(&operator)),
+                        .ok_or(LoxError::unexpected_type(operator)),
                     _ => unreachable!(),
                 }
             }
@@ -80,71 +422,101 @@ impl Interpreter {
                 // NOTE: The order of the left and right evaluations is significant. This
                 // determines the order in which binary expressions are evaluated. In our case:
                 // left-to-right.
-                let left = self.evaluate(*left, environment)?;
-                let right = self.evaluate(*right, environment)?;
+                let left = self.evaluate(left, environment)?;
+                let right = self.evaluate(right, environment)?;
+
+                // An instance on the left can overload an arithmetic operator with a method.
+                if let (Literal::Instance(instance), Some(name)) =
+                    (&left, overload_name(operator.token_type()))
+                {
+                    let method = instance.borrow().class().find_method(name).cloned();
+                    let Some(method) = method else {
+                        return Err(LoxError::unexpected_type(operator));
+                    };
+                    let method = Literal::Fun(Box::new(method.bind(left.clone())));
+                    return self.call(&method, environment, operator, vec![right]);
+                }
+
                 match operator.token_type() {
                     TokenType::Minus => left
                         .operate_number_binary(right, |l, r| l - r)
-                        .ok_or(LoxError::unexpected_type(&operator)),
+                        .ok_or(LoxError::unexpected_type(operator)),
                     TokenType::Plus => {
                         // FIXME: We can do this better by matching on the result of
                         // operate_number. Like, seriously, we can create a beautiful match here.
                         if left.number().is_some() && right.number().is_some() {
                             return left
                                 .operate_number_binary(right, |l, r| l + r)
-                                .ok_or(LoxError::unexpected_type(&operator));
+                                .ok_or(LoxError::unexpected_type(operator));
                         }
                         if left.string().is_some() && right.string().is_some() {
                             let right =
-                                right.string().ok_or(LoxError::unexpected_type(&operator))?;
+                                right.string().ok_or(LoxError::unexpected_type(operator))?;
                             return left
                                 .operate_string(|left| format!("{left}{right}"))
-                                .ok_or(LoxError::unexpected_type(&operator));
+                                .ok_or(LoxError::unexpected_type(operator));
                         }
-                        Err(LoxError::unexpected_type(&operator))
+                        Err(LoxError::unexpected_type(operator))
                     }
                     TokenType::Slash => left
                         .operate_number_binary(right, |l, r| l / r)
-                        .ok_or(LoxError::unexpected_type(&operator)),
+                        .ok_or(LoxError::unexpected_type(operator)),
                     TokenType::Star => left
                         .operate_number_binary(right, |l, r| l * r)
-                        .ok_or(LoxError::unexpected_type(&operator)),
-                    // FIXME: Use a macro for these suckers?
-                    TokenType::Greater => {
-                        use Literal::*;
-                        match (left, right) {
-                            (Number(l), Number(r)) => Some(Bool(l > r)),
-                            (Bool(l), Bool(r)) => Some(Bool(l > r)),
-                            (l, r) => Some(Bool(l.is_truthy() > r.is_truthy())),
-                        }
-                        .ok_or(LoxError::unexpected_type(&operator))
-                    }
-                    TokenType::GreaterEqual => {
-                        use Literal::*;
-                        match (left, right) {
-                            (Number(l), Number(r)) => Some(Bool(l >= r)),
-                            (Bool(l), Bool(r)) => Some(Bool(l >= r)),
-                            (l, r) => Some(Bool(l.is_truthy() >= r.is_truthy())),
-                        }
-                        .ok_or(LoxError::unexpected_type(&operator))
-                    }
-                    TokenType::Less => {
-                        use Literal::*;
-                        match (left, right) {
-                            (Number(l), Number(r)) => Some(Bool(l < r)),
-                            (Bool(l), Bool(r)) => Some(Bool(l < r)),
-                            (l, r) => Some(Bool(l.is_truthy() < r.is_truthy())),
-                        }
-                        .ok_or(LoxError::unexpected_type(&operator))
+                        .ok_or(LoxError::unexpected_type(operator)),
+                    TokenType::StarStar => left
+                        .operate_number_binary(right, Number::powf)
+                        .ok_or(LoxError::unexpected_type(operator)),
+                    // Follows Rust's `f64::rem`: the result has the sign of the dividend, and
+                    // a zero divisor gives NaN.
+                    TokenType::Percent => left
+                        .operate_number_binary(right, |l, r| l % r)
+                        .ok_or(LoxError::unexpected_type(operator)),
+                    // The bitwise operators only take whole numbers.
+                    TokenType::Ampersand => left
+                        .operate_integer_binary(right, |l, r| l & r)
+                        .ok_or(LoxError::unexpected_type(operator)),
+                    TokenType::Caret => left
+                        .operate_integer_binary(right, |l, r| l ^ r)
+                        .ok_or(LoxError::unexpected_type(operator)),
+                    TokenType::Pipe => left
+                        .operate_integer_binary(right, |l, r| l | r)
+                        .ok_or(LoxError::unexpected_type(operator)),
+                    TokenType::LessLess | TokenType::GreaterGreater => {
+                        let (Some(value), Some(amount)) = (left.number(), right.number()) else {
+                            return Err(LoxError::unexpected_type(operator));
+                        };
+                        // Unlike the bitwise operators, the shifts truncate their operands.
+                        let (value, amount) = (value as i64, amount as i64);
+                        let amount = u32::try_from(amount)
+                            .ok()
+                            .filter(|&amount| amount < i64::BITS)
+                            .ok_or_else(|| {
+                                LoxError::from_token(
+                                    operator,
+                                    format!("Can't shift by {amount}, expect 0 to 63."),
+                                )
+                            })?;
+                        let shifted = match operator.token_type() {
+                            TokenType::LessLess => value << amount,
+                            _ => value >> amount,
+                        };
+                        Ok(Literal::Number(shifted as Number))
                     }
-                    TokenType::LessEqual => {
-                        use Literal::*;
-                        match (left, right) {
-                            (Number(l), Number(r)) => Some(Bool(l <= r)),
-                            (Bool(l), Bool(r)) => Some(Bool(l <= r)),
-                            (l, r) => Some(Bool(l.is_truthy() <= r.is_truthy())),
-                        }
-                        .ok_or(LoxError::unexpected_type(&operator))
+                    // Only numbers are ordered. Any value can be compared for equality, though.
+                    TokenType::Greater
+                    | TokenType::GreaterEqual
+                    | TokenType::Less
+                    | TokenType::LessEqual => {
+                        let compare: fn(&Number, &Number) -> bool = match operator.token_type() {
+                            TokenType::Greater => Number::gt,
+                            TokenType::GreaterEqual => Number::ge,
+                            TokenType::Less => Number::lt,
+                            _ => Number::le,
+                        };
+                        left.compare_numbers(right, compare).ok_or_else(|| {
+                            LoxError::from_token(operator, "Operands must be numbers.".to_string())
+                        })
                     }
                     // This unwrap should be fine because we apply it to the result of is_equal,
                     // which is always Literal::Bool(...), so the type is always as expected.
@@ -152,6 +524,7 @@ impl Interpreter {
                         Ok(Literal::is_equal(left, right).operate_bool(|b| !b).unwrap())
                     }
                     TokenType::EqualEqual => Ok(Literal::is_equal(left, right)),
+                    TokenType::Is => Ok(Literal::Bool(Literal::is_identical(&left, &right))),
                     _ => todo!(),
                 }
             }
@@ -160,56 +533,441 @@ impl Interpreter {
                 paren,
                 arguments,
             } => {
-                let callee = self.evaluate(*callee, environment)?;
-                let mut argument_literals = Vec::new();
-                for argument in arguments {
-                    argument_literals.push(self.evaluate(argument, environment)?);
-                }
-                let arguments = argument_literals;
-
-                let function = callee.callable().ok_or(LoxError::from_token(
-                    &paren,
-                    "Can only call functions and classes.".to_string(),
-                ))?;
+                let callee = self.evaluate(callee, environment)?;
+                let arguments = self.evaluate_elements(arguments, environment)?;
+                let arguments = arguments
+                    .into_iter()
+                    .map(|argument| self.pass(argument))
+                    .collect();
 
-                if arguments.len() != function.arity() {
-                    return Err(LoxError::from_token(
-                        &paren,
-                        format!(
-                            "Expected {arity} + arguments but got {len}.",
-                            arity = function.arity(),
-                            len = arguments.len()
-                        ),
-                    ));
+                self.call(&callee, environment, paren, arguments)
+            }
+            Expr::Get { object, name } => match self.evaluate(object, environment)? {
+                Literal::Instance(instance) => Instance::get(&instance, name),
+                Literal::Class(class) => class.find_static(name.lexeme()).ok_or_else(|| {
+                    LoxError::from_token(
+                        name,
+                        format!("Undefined static field '{}'.", name.lexeme()),
+                    )
+                }),
+                receiver @ (Literal::List(_) | Literal::String(_)) => {
+                    natives::method(&receiver, name.lexeme())
+                        .map(|method| Literal::Native(method.bind(receiver)))
+                        .ok_or_else(|| {
+                            LoxError::from_token(
+                                name,
+                                format!("Undefined property '{}'.", name.lexeme()),
+                            )
+                        })
+                }
+                _ => Err(LoxError::from_token(
+                    name,
+                    "Only instances have properties.".to_string(),
+                )),
+            },
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                let instance = match self.evaluate(object, environment)? {
+                    Literal::Instance(instance) => instance,
+                    Literal::Class(class) if class.find_static(name.lexeme()).is_some() => {
+                        return Err(LoxError::from_token(
+                            name,
+                            format!("Can't assign to static field '{}'.", name.lexeme()),
+                        ))
+                    }
+                    _ => {
+                        return Err(LoxError::from_token(
+                            name,
+                            "Only instances have fields.".to_string(),
+                        ))
+                    }
+                };
+                let value = self.evaluate(value, environment)?;
+                instance.borrow_mut().set(name.lexeme(), value.clone());
+                Ok(value)
+            }
+            Expr::List { elements } => {
+                let values = self.evaluate_elements(elements, environment)?;
+                self.count_allocation(Allocation::List);
+                Ok(Literal::List(Rc::new(RefCell::new(values))))
+            }
+            Expr::Map { brace, entries } => {
+                let mut map = HashMap::new();
+                for entry in entries {
+                    match entry {
+                        MapEntry::Pair(key, value) => {
+                            let key = self.evaluate(key, environment)?;
+                            let key = map_key(&key, brace)?.clone();
+                            let value = self.evaluate(value, environment)?;
+                            map.insert(key, value);
+                        }
+                        MapEntry::Spread(spread) => {
+                            let Expr::Spread {
+                                ellipsis,
+                                expression,
+                            } = spread
+                            else {
+                                unreachable!("the parser only puts spreads in a map spread")
+                            };
+                            match self.evaluate(expression, environment)? {
+                                Literal::Map(entries) => map.extend(
+                                    entries
+                                        .borrow()
+                                        .iter()
+                                        .map(|(key, value)| (key.clone(), value.clone())),
+                                ),
+                                _ => {
+                                    return Err(LoxError::from_token(
+                                        ellipsis,
+                                        "Can only spread a map into a map.".to_string(),
+                                    ))
+                                }
+                            }
+                        }
+                    }
+                }
+                self.count_allocation(Allocation::Map);
+                Ok(Literal::Map(Rc::new(RefCell::new(map))))
+            }
+            // Spreads are expanded by the list, map or call they are in.
+            Expr::Spread { ellipsis, .. } => Err(LoxError::from_token(
+                ellipsis,
+                "Can only spread in a list, map or call.".to_string(),
+            )),
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object = self.evaluate(object, environment)?;
+                let index = self.evaluate(index, environment)?;
+                match object {
+                    Literal::List(elements) => {
+                        let elements = elements.borrow();
+                        let index = list_index(&index, elements.len(), bracket)?;
+                        Ok(elements[index].clone())
+                    }
+                    // A key that is not in the map gives nil.
+                    Literal::Map(entries) => Ok(entries
+                        .borrow()
+                        .get(map_key(&index, bracket)?)
+                        .cloned()
+                        .unwrap_or(Literal::Nil)),
+                    // Unlike a map, an instance has no property by a name it was never given.
+                    Literal::Instance(instance) => {
+                        let name = property_name(&index, bracket)?;
+                        Instance::property(&instance, name).ok_or_else(|| {
+                            LoxError::from_token(bracket, format!("Undefined property '{name}'."))
+                        })
+                    }
+                    _ => Err(LoxError::from_token(
+                        bracket,
+                        "Can only index lists, maps and instances.".to_string(),
+                    )),
+                }
+            }
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                let object = self.evaluate(object, environment)?;
+                let index = self.evaluate(index, environment)?;
+                let value = self.evaluate(value, environment)?;
+                match object {
+                    Literal::List(elements) => {
+                        let mut elements = elements.borrow_mut();
+                        let index = list_index(&index, elements.len(), bracket)?;
+                        elements[index] = value.clone();
+                    }
+                    Literal::Map(entries) => {
+                        let key = map_key(&index, bracket)?.clone();
+                        entries.borrow_mut().insert(key, value.clone());
+                    }
+                    Literal::Instance(instance) => {
+                        let name = property_name(&index, bracket)?;
+                        instance.borrow_mut().set(name, value.clone());
+                    }
+                    _ => {
+                        return Err(LoxError::from_token(
+                            bracket,
+                            "Can only index lists, maps and instances.".to_string(),
+                        ))
+                    }
+                }
+                Ok(value)
+            }
+            Expr::Grouping { expression } => self.evaluate(expression, environment),
+            Expr::Match {
+                subject,
+                arms,
+                wildcard,
+                ..
+            } => {
+                let subject = self.evaluate(subject, environment)?;
+                let body = arms
+                    .iter()
+                    .find(|arm| Literal::is_equal(subject.clone(), arm.pattern.clone()).is_truthy())
+                    .map_or(wildcard.as_ref(), |arm| &arm.body);
+                self.evaluate(body, environment)
+            }
+            Expr::Loop { body, .. } => loop {
+                self.check_deadlines()?;
+                match self.execute_block(body, environment) {
+                    Err(e) if e.is_break_unwind() => {
+                        return Ok(self.break_value.take().unwrap_or(Literal::Nil))
+                    }
+                    Err(e) if e.is_continue_unwind() => {}
+                    result => {
+                        result?;
+                    }
+                }
+            },
+            Expr::Switch {
+                subject,
+                cases,
+                default,
+                ..
+            } => {
+                let subject = self.evaluate(subject, environment)?;
+                let mut matched = cases.len();
+                for (n, case) in cases.iter().enumerate() {
+                    let value = self.evaluate(&case.value, environment)?;
+                    if Literal::is_equal(subject.clone(), value).is_truthy() {
+                        matched = n;
+                        break;
+                    }
                 }
 
-                match function.call(self, environment, arguments) {
-                    Ok(v) => Ok(v),
-                    Err(e) if e.message == "RETURN".to_string() => {
-                        let return_value = self.return_value.clone().unwrap();
-                        self.return_value = None;
-                        Ok(return_value)
+                // A fallthrough continues with the next body. The parser makes sure that the
+                // default body, which comes last, can't fall through.
+                let bodies = cases.iter().map(|case| &case.body).chain([default]);
+                for body in bodies.skip(matched) {
+                    match self.execute_block(body, environment) {
+                        Err(e) if e.is_fallthrough_unwind() => continue,
+                        result => return result,
                     }
-                    Err(e) => Err(e),
                 }
+                unreachable!("the default case of a switch can't fall through")
             }
-            Expr::Grouping { expression } => self.evaluate(*expression, environment),
+        }
+    }
+
+    /// Evaluate the elements of a list literal or the arguments of a call, splicing in the
+    /// elements of any list that is spread.
+    fn evaluate_elements(
+        &mut self,
+        elements: &[Expr],
+        environment: &Environment,
+    ) -> Result<Vec<Literal>, LoxError> {
+        let mut values = Vec::new();
+        for element in elements {
+            match element {
+                Expr::Spread {
+                    ellipsis,
+                    expression,
+                } => match self.evaluate(expression, environment)? {
+                    Literal::List(elements) => values.extend(elements.borrow().iter().cloned()),
+                    _ => {
+                        return Err(LoxError::from_token(
+                            ellipsis,
+                            "Can only spread a list.".to_string(),
+                        ))
+                    }
+                },
+                element => values.push(self.evaluate(element, environment)?),
+            }
+        }
+        Ok(values)
+    }
+
+    /// Get the value of the variable `name`, from the scope the resolver found it in.
+    fn look_up_variable(
+        &self,
+        name: &Token,
+        depth: &Depth,
+        environment: &Environment,
+    ) -> Result<Literal, LoxError> {
+        match depth.get() {
+            Some(depth) => environment.get_at(depth, name),
+            None => self.globals.get_var(name),
+        }
+    }
+
+    /// Call the `callee` with the given arguments, checking that it is callable and that the
+    /// number of arguments is right. Natives use this to call back into Lox functions.
+    pub(crate) fn call(
+        &mut self,
+        callee: &Literal,
+        environment: &Environment,
+        paren: &Token,
+        arguments: Vec<Literal>,
+    ) -> Result<Literal, LoxError> {
+        let function = callee.callable().ok_or(LoxError::from_token(
+            paren,
+            "Can only call functions and classes.".to_string(),
+        ))?;
+
+        if !(function.min_arity()..=function.max_arity()).contains(&arguments.len()) {
+            return Err(LoxError::from_token(
+                paren,
+                format!(
+                    "Expected {arity} arguments but got {len}.",
+                    arity = function.describe_arity(),
+                    len = arguments.len()
+                ),
+            ));
+        }
+
+        if let (true, Literal::Fun(fun)) = (self.tracing, callee) {
+            let arguments = arguments
+                .iter()
+                .map(|argument| argument.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            eprintln!("-> {}({arguments})", fun.name().lexeme());
+        }
+
+        self.check_deadlines()?;
+        let value = function.call(self, environment, paren, arguments)?;
+
+        if let (true, Literal::Fun(fun)) = (self.tracing, callee) {
+            eprintln!("<- {} = {value}", fun.name().lexeme());
+        }
+        Ok(value)
+    }
+
+    /// Turn `value` into the string that printing it shows. An instance whose class has a
+    /// `toString` method is shown as what that returns, and any other value by its default
+    /// representation, which `repr` always gives.
+    fn stringify(&mut self, value: Literal, environment: &Environment) -> Result<String, LoxError> {
+        let Literal::Instance(instance) = &value else {
+            return Ok(value.to_string());
+        };
+        let method = instance.borrow().class().find_method("toString").cloned();
+        match method {
+            Some(method) => {
+                let name = method.name().clone();
+                let method = Literal::Fun(Box::new(method.bind(value)));
+                Ok(self
+                    .call(&method, environment, &name, Vec::new())?
+                    .to_string())
+            }
+            None => Ok(value.to_string()),
         }
     }
 
     fn execute(
         &mut self,
-        statement: Stmt,
-        environment: &mut Environment,
+        statement: &Stmt,
+        environment: &Environment,
     ) -> Result<Literal, LoxError> {
         match statement {
+            Stmt::Assert {
+                keyword,
+                condition,
+                message,
+                source,
+            } => {
+                if self.evaluate(condition, environment)?.is_truthy() {
+                    return Ok(Literal::Nil);
+                }
+
+                let message = match message {
+                    Some(message) => self.evaluate(message, environment)?.to_string(),
+                    None => source.clone(),
+                };
+                Err(LoxError::from_token(
+                    keyword,
+                    format!("Assertion failed: {message}"),
+                ))
+            }
             Stmt::Block { statements } => {
                 self.execute_block(statements, environment)?;
                 Ok(Literal::Nil)
             }
+            Stmt::Break { keyword, value } => {
+                let value = match value {
+                    Some(val) => self.evaluate(val, environment)?,
+                    None => Literal::Nil,
+                };
+                self.break_value = Some(value);
+                Err(LoxError::break_unwind(keyword))
+            }
+            Stmt::Continue { keyword } => Err(LoxError::continue_unwind(keyword)),
             Stmt::Expression { expression } => self.evaluate(expression, environment),
+            Stmt::Fallthrough { keyword } => Err(LoxError::fallthrough_unwind(keyword)),
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                statics,
+            } => {
+                let superclass = match superclass {
+                    Some(expr @ Expr::Variable { name, .. }) => {
+                        match self.evaluate(expr, environment)? {
+                            Literal::Class(class) => Some(class),
+                            _ => {
+                                return Err(LoxError::from_token(
+                                    name,
+                                    "Superclass must be a class.".to_string(),
+                                ))
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+                let methods = methods
+                    .iter()
+                    .filter_map(|method| Function::method(method, environment))
+                    .map(|method| (method.name().lexeme().to_string(), method))
+                    .collect();
+                // Static fields are evaluated once, when the class is defined.
+                let mut fields = HashMap::new();
+                for field in statics {
+                    if let Stmt::Var {
+                        name,
+                        initializer: Some(initializer),
+                    } = field
+                    {
+                        let value = self.evaluate(initializer, environment)?;
+                        fields.insert(name.lexeme().to_string(), value);
+                    }
+                }
+                let class = Class::new(name.clone(), superclass, methods, fields);
+                environment.define(name.lexeme().to_string(), Literal::Class(Rc::new(class)));
+
+                Ok(Literal::Nil)
+            }
+            Stmt::Enum { name, variants } => {
+                let class = Rc::new(Class::new(
+                    name.clone(),
+                    None,
+                    HashMap::new(),
+                    HashMap::new(),
+                ));
+                // Every variant is its own instance, so variants are only equal to themselves.
+                for (ordinal, variant) in variants.iter().enumerate() {
+                    let mut instance = Instance::new(Rc::clone(&class));
+                    instance.set("name", Literal::String(variant.lexeme().to_string()));
+                    instance.set("ordinal", Literal::Number(ordinal as f64));
+                    self.count_allocation(Allocation::Instance);
+                    class.define_static(
+                        variant.lexeme().to_string(),
+                        Literal::Instance(Rc::new(RefCell::new(instance))),
+                    );
+                }
+                environment.define(name.lexeme().to_string(), Literal::Class(class));
+
+                Ok(Literal::Nil)
+            }
             function @ Stmt::Function { .. } => {
-                let function = Function::new(function).unwrap();
+                let function = Function::new(function, environment).unwrap();
+                self.count_allocation(Allocation::Function);
                 environment.define(
                     function.name().lexeme().to_string(),
                     Literal::Fun(Box::new(function)),
@@ -226,15 +984,23 @@ impl Interpreter {
                 // based languages. If, in this implementation, returns the result literal from
                 // the executed branch.
                 if self.evaluate(condition, environment)?.is_truthy() {
-                    self.execute(*then_branch, environment)
+                    self.execute(then_branch, environment)
                 } else if let Some(else_branch) = else_branch {
-                    self.execute(*else_branch, environment)
+                    self.execute(else_branch, environment)
                 } else {
                     Ok(Literal::Nil)
                 }
             }
             Stmt::Print { expression } => {
-                println!("{}", self.evaluate(expression, environment)?);
+                let value = self.evaluate(expression, environment)?;
+                let value = self.stringify(value, environment)?;
+                self.write_output(&format!("{value}\n"));
+                Ok(Literal::Nil)
+            }
+            Stmt::EPrint { expression } => {
+                let value = self.evaluate(expression, environment)?;
+                let value = self.stringify(value, environment)?;
+                self.write_to(Sink::Stderr, &format!("{value}\n"));
                 Ok(Literal::Nil)
             }
             Stmt::Return { keyword, value } => {
@@ -243,56 +1009,272 @@ impl Interpreter {
                     None => Literal::Nil,
                 };
                 self.return_value = Some(value.clone());
-                Err(LoxError::return_unwind(&keyword))
+                Err(LoxError::return_unwind(keyword))
             }
             Stmt::Var { name, initializer } => {
                 let value = if let Some(init) = initializer {
-                    self.evaluate(init, environment)?
+                    let value = self.evaluate(init, environment)?;
+                    self.pass(value)
                 } else {
                     Literal::Nil
                 };
                 environment.define(name.lexeme().to_string(), value);
                 Ok(Literal::Nil)
             }
-            Stmt::While { condition, body } => {
-                // TODO: These clones might actually give us undesirable and incorrect behaviour.
-                while self.evaluate(condition.clone(), environment)?.is_truthy() {
-                    self.execute(*body.clone(), environment)?;
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                else_branch,
+            } => {
+                while self.evaluate(condition, environment)?.is_truthy() {
+                    self.check_deadlines()?;
+                    match self.execute(body, environment) {
+                        // Only a loop expression has a value to break out with, so any value
+                        // given here is dropped.
+                        Err(e) if e.is_break_unwind() => {
+                            self.break_value = None;
+                            return Ok(Literal::Nil);
+                        }
+                        Err(e) if e.is_continue_unwind() => {}
+                        result => {
+                            result?;
+                        }
+                    };
+                    if let Some(increment) = increment {
+                        self.evaluate(increment, environment)?;
+                    }
+                }
+                if let Some(else_branch) = else_branch {
+                    self.execute(else_branch, environment)?;
                 }
                 Ok(Literal::Nil)
             }
+            Stmt::With {
+                keyword,
+                name,
+                initializer,
+                body,
+            } => {
+                let resource = self.evaluate(initializer, environment)?;
+                let close = match &resource {
+                    Literal::Instance(instance) => Instance::property(instance, "close"),
+                    _ => None,
+                };
+                let Some(close) = close else {
+                    return Err(LoxError::from_token(
+                        keyword,
+                        "A resource must be an instance with a 'close' method.".to_string(),
+                    ));
+                };
+
+                let with_env = Environment::from_parent(environment);
+                with_env.define(name.lexeme().to_string(), resource);
+                let result = self.execute_block(body, &with_env);
+
+                // The resource is closed also when the body fails, or is left by a return or
+                // break. Their values are set aside, so closing doesn't overwrite them.
+                let return_value = self.return_value.take();
+                let break_value = self.break_value.take();
+                self.call(&close, environment, keyword, Vec::new())?;
+                self.return_value = return_value;
+                self.break_value = break_value;
+
+                result.map(|_| Literal::Nil)
+            }
         }
     }
 
     pub(crate) fn execute_block(
         &mut self,
-        statements: Vec<Stmt>,
-        environment: &mut Environment,
-    ) -> Result<(), LoxError> {
-        let mut block_env = Environment::from_parent(environment);
-        for statement in statements {
-            self.execute(statement, &mut block_env)?;
-        }
-        // std::mem::swap(&mut *block_env.fallback.unwrap(), environment);
-        *environment = std::mem::take(&mut block_env.fallback()).unwrap();
-        Ok(())
+        statements: &[Stmt],
+        environment: &Environment,
+    ) -> Result<Literal, LoxError> {
+        let block_env = Environment::from_parent(environment);
+        let result = statements.iter().try_fold(Literal::Nil, |_, statement| {
+            let value = self.execute(statement, &block_env)?;
+            // A block evaluates to its trailing expression, if it has one.
+            Ok(match statement {
+                Stmt::Expression { .. } => value,
+                _ => Literal::Nil,
+            })
+        });
+        result
     }
 
-    pub(crate) fn interpret(&mut self, statements: Vec<Stmt>) -> Result<String, LoxError> {
-        let mut environment = Environment::new();
-        self.interpret_with_env(statements, &mut environment)
+    /// Interpret the statements in the global environment, returning the value of the last one if
+    /// it is an expression statement.
+    ///
+    /// Anything the statements define remains available to later calls, which is what allows the
+    /// REPL to build on previous lines.
+    pub(crate) fn interpret(&mut self, statements: Vec<Stmt>) -> Result<Option<Literal>, LoxError> {
+        // Another handle to the same scope, so the interpreter itself can be borrowed meanwhile.
+        let globals = self.globals.clone();
+        self.interpret_with_env(statements, &globals)
     }
 
-    pub(crate) fn interpret_with_env(
+    fn interpret_with_env(
         &mut self,
         statements: Vec<Stmt>,
-        environment: &mut Environment,
+        environment: &Environment,
+    ) -> Result<Option<Literal>, LoxError> {
+        let mut value = None;
+        for statement in &statements {
+            let result = self.execute(statement, environment)?;
+            value = match statement {
+                Stmt::Expression { .. } => Some(result),
+                _ => None,
+            };
+        }
+
+        Ok(value)
+    }
+}
+
+/// The name of the method that overloads the arithmetic operator `operator`, if it is one.
+fn overload_name(operator: TokenType) -> Option<&'static str> {
+    match operator {
+        TokenType::Plus => Some("__add__"),
+        TokenType::Minus => Some("__sub__"),
+        TokenType::Star => Some("__mul__"),
+        TokenType::Slash => Some("__div__"),
+        TokenType::Percent => Some("__mod__"),
+        TokenType::StarStar => Some("__pow__"),
+        _ => None,
+    }
+}
+
+/// Check that `index` is a whole number that is a valid index into a list of length `len`.
+fn list_index(index: &Literal, len: usize, bracket: &Token) -> Result<usize, LoxError> {
+    let Some(index) = index.integer() else {
+        return Err(LoxError::from_token(
+            bracket,
+            "List index must be a whole number.".to_string(),
+        ));
+    };
+    usize::try_from(index)
+        .ok()
+        .filter(|&index| index < len)
+        .ok_or_else(|| {
+            LoxError::from_token(
+                bracket,
+                format!("Index {index} is out of bounds for a list of length {len}."),
+            )
+        })
+}
+
+/// Check that `key` is a string, which is what maps are indexed by.
+fn map_key<'a>(key: &'a Literal, token: &Token) -> Result<&'a String, LoxError> {
+    key.string()
+        .ok_or_else(|| LoxError::from_token(token, "Map keys must be strings.".to_string()))
+}
+
+/// Check that `name` is a string, which is what the properties of an instance are indexed by.
+fn property_name<'a>(name: &'a Literal, token: &Token) -> Result<&'a String, LoxError> {
+    name.string()
+        .ok_or_else(|| LoxError::from_token(token, "Property names must be strings.".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use crate::scanner::Scanner;
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        let tokens = Scanner::new(source, 1).scan_tokens().unwrap();
+        let statements = Parser::new(tokens, 1).parse().unwrap();
+        Resolver::new(1).resolve(&statements).unwrap();
+        statements
+    }
+
+    #[test]
+    fn eprint_writes_to_stderr_rather_than_stdout() {
+        let mut interpreter = Interpreter::new();
+        let statements = parse(r#"print "out"; eprint "err";"#);
+        let mut stdout = String::new();
+        let stderr = interpreter
+            .capture_output(Sink::Stderr, |interpreter| {
+                stdout = interpreter.capture_output(Sink::Stdout, |interpreter| {
+                    interpreter.interpret(statements)?;
+                    Ok(Literal::Nil)
+                })?;
+                Ok(Literal::Nil)
+            })
+            .unwrap();
+        assert_eq!(stdout, "out\n");
+        assert_eq!(stderr, "err\n");
+    }
+
+    /// Interpret `source` with `input` in place of stdin, returning the value it ends with.
+    fn interpret_with_input(
+        source: &str,
+        input: &str,
+        sandboxed: bool,
     ) -> Result<String, LoxError> {
-        for statement in statements {
-            self.execute(statement, environment)?;
+        let mut interpreter = Interpreter::new();
+        interpreter.set_input(std::io::Cursor::new(input.to_string()));
+        interpreter.set_sandboxed(sandboxed);
+        let value = interpreter.interpret(parse(source))?;
+        Ok(value.unwrap_or(Literal::Nil).to_string())
+    }
+
+    #[test]
+    fn read_all_reads_what_is_left_of_the_input() {
+        let source = "var first = input(); [first, readAll(), readAll()];";
+        let value = interpret_with_input(source, "one\ntwo\n\nthree\n", false);
+        assert_eq!(value.unwrap(), "[one, two\n\nthree\n, ]");
+    }
+
+    #[test]
+    fn lines_returns_the_lines_that_are_left() {
+        let value = interpret_with_input("input(); lines();", "one\ntwo\r\n\nthree", false);
+        assert_eq!(value.unwrap(), "[two, , three]");
+        let value = interpret_with_input("lines();", "", false);
+        assert_eq!(value.unwrap(), "[]");
+    }
+
+    #[test]
+    fn reading_input_is_refused_in_the_sandbox() {
+        for native in ["readAll", "lines"] {
+            let error = interpret_with_input(&format!("{native}();"), "one\n", true).unwrap_err();
+            assert!(error
+                .render(false)
+                .contains(&format!("Can't use '{native}' in sandbox mode.")));
         }
+    }
+
+    #[test]
+    fn registered_natives_can_be_called_from_lox() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_native(
+            "double",
+            1,
+            Box::new(|_, arguments| match arguments[0] {
+                Literal::Number(n) => Ok(Literal::Number(n * 2.0)),
+                _ => Ok(Literal::Nil),
+            }),
+        );
+        let value = interpreter.interpret(parse(
+            "fun quadruple(n) { double(double(n)) } quadruple(5) + double(1);",
+        ));
+        assert_eq!(value.unwrap().unwrap().to_string(), "22");
+        let value = interpreter.interpret(parse(r#"double("two");"#));
+        assert_eq!(value.unwrap().unwrap().to_string(), "nil");
+    }
 
-        // TODO this is wrong of course. (temp)
-        Ok(String::new())
+    #[test]
+    fn defined_natives_can_capture_state() {
+        let mut interpreter = Interpreter::new();
+        let calls = Rc::new(std::cell::Cell::new(0));
+        let counted = Rc::clone(&calls);
+        interpreter.define_native("tick", 0, move |_, _, _, _| {
+            counted.set(counted.get() + 1);
+            Ok(Literal::Number(counted.get() as Number))
+        });
+        let value = interpreter.interpret(parse("tick(); tick(); tick();"));
+        assert_eq!(value.unwrap().unwrap().to_string(), "3");
+        assert_eq!(calls.get(), 3);
     }
 }
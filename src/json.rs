@@ -0,0 +1,439 @@
+use std::fmt::Display;
+
+use crate::ast::{Expr, MapEntry, Stmt};
+use crate::token::{Literal, Number, Token};
+
+/// A minimal JSON value. Just enough to dump the syntax tree for consumption by external tools.
+#[derive(Debug, Clone)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    fn string(s: impl ToString) -> Self {
+        Self::String(s.to_string())
+    }
+
+    /// An object describing a syntax tree node of the given kind, with the given fields.
+    fn node(node: &'static str, fields: Vec<(&'static str, Json)>) -> Self {
+        let mut object = vec![("node", Json::string(node))];
+        object.extend(fields);
+        Self::Object(object)
+    }
+
+    fn array<T>(items: &[T], f: impl Fn(&T) -> Json) -> Self {
+        Self::Array(items.iter().map(f).collect())
+    }
+
+    fn option<T>(item: &Option<T>, f: impl Fn(&T) -> Json) -> Self {
+        item.as_ref().map(f).unwrap_or(Json::Null)
+    }
+}
+
+impl Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{b}"),
+            // JSON has no representation for NaN or the infinities.
+            Json::Number(n) if !n.is_finite() => write!(f, "null"),
+            Json::Number(n) => write!(f, "{n}"),
+            Json::String(s) => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                        c => write!(f, "{c}")?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (n, item) in items.iter().enumerate() {
+                    if n > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (n, (key, value)) in fields.iter().enumerate() {
+                    if n > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{value}", Json::string(key))?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl From<&Token> for Json {
+    fn from(token: &Token) -> Self {
+        Json::Object(vec![
+            ("type", Json::string(format!("{:?}", token.token_type()))),
+            ("lexeme", Json::string(token.lexeme())),
+            ("line", Json::Number(token.line() as Number)),
+            ("col", Json::Number(token.col() as Number)),
+        ])
+    }
+}
+
+impl From<&Literal> for Json {
+    fn from(literal: &Literal) -> Self {
+        match literal {
+            Literal::String(s) => Json::string(s),
+            Literal::Number(n) => Json::Number(*n),
+            Literal::Nil => Json::Null,
+            Literal::Bool(b) => Json::Bool(*b),
+            object @ (Literal::Fun(_)
+            | Literal::Native(_)
+            | Literal::Class(_)
+            | Literal::Instance(_)
+            | Literal::Map(_)) => Json::string(object),
+            Literal::List(elements) => Json::array(&elements.borrow(), |item| item.into()),
+        }
+    }
+}
+
+impl From<&Expr> for Json {
+    fn from(expr: &Expr) -> Self {
+        match expr {
+            Expr::Literal { value } => Json::node("Literal", vec![("value", value.into())]),
+            Expr::Variable { name, .. } => Json::node("Variable", vec![("name", name.into())]),
+            Expr::This { keyword, .. } => Json::node("This", vec![("keyword", keyword.into())]),
+            Expr::Assign { name, value, .. } => Json::node(
+                "Assign",
+                vec![("name", name.into()), ("value", value.as_ref().into())],
+            ),
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => Json::node(
+                "Logical",
+                vec![
+                    ("left", left.as_ref().into()),
+                    ("operator", operator.into()),
+                    ("right", right.as_ref().into()),
+                ],
+            ),
+            Expr::Unary { operator, right } => Json::node(
+                "Unary",
+                vec![
+                    ("operator", operator.into()),
+                    ("right", right.as_ref().into()),
+                ],
+            ),
+            Expr::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+            } => Json::node(
+                "Ternary",
+                vec![
+                    ("condition", condition.as_ref().into()),
+                    ("then_branch", then_branch.as_ref().into()),
+                    ("else_branch", else_branch.as_ref().into()),
+                ],
+            ),
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => Json::node(
+                "Binary",
+                vec![
+                    ("left", left.as_ref().into()),
+                    ("operator", operator.into()),
+                    ("right", right.as_ref().into()),
+                ],
+            ),
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => Json::node(
+                "Call",
+                vec![
+                    ("callee", callee.as_ref().into()),
+                    ("paren", paren.into()),
+                    ("arguments", Json::array(arguments, |item| item.into())),
+                ],
+            ),
+            Expr::Get { object, name } => Json::node(
+                "Get",
+                vec![("object", object.as_ref().into()), ("name", name.into())],
+            ),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => Json::node(
+                "Set",
+                vec![
+                    ("object", object.as_ref().into()),
+                    ("name", name.into()),
+                    ("value", value.as_ref().into()),
+                ],
+            ),
+            Expr::List { elements } => Json::node(
+                "List",
+                vec![("elements", Json::array(elements, |item| item.into()))],
+            ),
+            Expr::Map { brace, entries } => Json::node(
+                "Map",
+                vec![
+                    ("brace", brace.into()),
+                    (
+                        "entries",
+                        Json::array(entries, |entry| match entry {
+                            MapEntry::Pair(key, value) => {
+                                Json::Object(vec![("key", key.into()), ("value", value.into())])
+                            }
+                            MapEntry::Spread(spread) => spread.into(),
+                        }),
+                    ),
+                ],
+            ),
+            Expr::Spread {
+                ellipsis,
+                expression,
+            } => Json::node(
+                "Spread",
+                vec![
+                    ("ellipsis", ellipsis.into()),
+                    ("expression", expression.as_ref().into()),
+                ],
+            ),
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => Json::node(
+                "Index",
+                vec![
+                    ("object", object.as_ref().into()),
+                    ("bracket", bracket.into()),
+                    ("index", index.as_ref().into()),
+                ],
+            ),
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => Json::node(
+                "SetIndex",
+                vec![
+                    ("object", object.as_ref().into()),
+                    ("bracket", bracket.into()),
+                    ("index", index.as_ref().into()),
+                    ("value", value.as_ref().into()),
+                ],
+            ),
+            Expr::Grouping { expression } => {
+                Json::node("Grouping", vec![("expression", expression.as_ref().into())])
+            }
+            Expr::Match {
+                keyword,
+                subject,
+                arms,
+                wildcard,
+            } => Json::node(
+                "Match",
+                vec![
+                    ("keyword", keyword.into()),
+                    ("subject", subject.as_ref().into()),
+                    (
+                        "arms",
+                        Json::array(arms, |arm| {
+                            Json::Object(vec![
+                                ("pattern", (&arm.pattern).into()),
+                                ("body", (&arm.body).into()),
+                            ])
+                        }),
+                    ),
+                    ("wildcard", wildcard.as_ref().into()),
+                ],
+            ),
+            Expr::Loop { keyword, body } => Json::node(
+                "Loop",
+                vec![
+                    ("keyword", keyword.into()),
+                    ("body", Json::array(body, |item| item.into())),
+                ],
+            ),
+            Expr::Switch {
+                keyword,
+                subject,
+                cases,
+                default,
+            } => Json::node(
+                "Switch",
+                vec![
+                    ("keyword", keyword.into()),
+                    ("subject", subject.as_ref().into()),
+                    (
+                        "cases",
+                        Json::array(cases, |case| {
+                            Json::Object(vec![
+                                ("value", (&case.value).into()),
+                                ("body", Json::array(&case.body, |item| item.into())),
+                            ])
+                        }),
+                    ),
+                    ("default", Json::array(default, |item| item.into())),
+                ],
+            ),
+        }
+    }
+}
+
+impl From<&Stmt> for Json {
+    fn from(stmt: &Stmt) -> Self {
+        match stmt {
+            Stmt::Assert {
+                keyword,
+                condition,
+                message,
+                source,
+            } => Json::node(
+                "Assert",
+                vec![
+                    ("keyword", keyword.into()),
+                    ("condition", condition.into()),
+                    ("message", Json::option(message, |item| item.into())),
+                    ("source", Json::string(source)),
+                ],
+            ),
+            Stmt::Block { statements } => Json::node(
+                "Block",
+                vec![("statements", Json::array(statements, |item| item.into()))],
+            ),
+            Stmt::Break { keyword, value } => Json::node(
+                "Break",
+                vec![
+                    ("keyword", keyword.into()),
+                    ("value", Json::option(value, |item| item.into())),
+                ],
+            ),
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                statics,
+            } => Json::node(
+                "Class",
+                vec![
+                    ("name", name.into()),
+                    ("superclass", Json::option(superclass, |item| item.into())),
+                    ("methods", Json::array(methods, |item| item.into())),
+                    ("statics", Json::array(statics, |item| item.into())),
+                ],
+            ),
+            Stmt::Enum { name, variants } => Json::node(
+                "Enum",
+                vec![
+                    ("name", name.into()),
+                    ("variants", Json::array(variants, |item| item.into())),
+                ],
+            ),
+            Stmt::Continue { keyword } => Json::node("Continue", vec![("keyword", keyword.into())]),
+            Stmt::Expression { expression } => {
+                Json::node("Expression", vec![("expression", expression.into())])
+            }
+            Stmt::Fallthrough { keyword } => {
+                Json::node("Fallthrough", vec![("keyword", keyword.into())])
+            }
+            Stmt::Function { name, params, body } => Json::node(
+                "Function",
+                vec![
+                    ("name", name.into()),
+                    ("params", Json::array(params, |item| item.into())),
+                    ("body", Json::array(body, |item| item.into())),
+                ],
+            ),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => Json::node(
+                "If",
+                vec![
+                    ("condition", condition.into()),
+                    ("then_branch", then_branch.as_ref().into()),
+                    (
+                        "else_branch",
+                        Json::option(else_branch, |stmt| stmt.as_ref().into()),
+                    ),
+                ],
+            ),
+            Stmt::Print { expression } => {
+                Json::node("Print", vec![("expression", expression.into())])
+            }
+            Stmt::EPrint { expression } => {
+                Json::node("EPrint", vec![("expression", expression.into())])
+            }
+            Stmt::Return { keyword, value } => Json::node(
+                "Return",
+                vec![
+                    ("keyword", keyword.into()),
+                    ("value", Json::option(value, |item| item.into())),
+                ],
+            ),
+            Stmt::Var { name, initializer } => Json::node(
+                "Var",
+                vec![
+                    ("name", name.into()),
+                    ("initializer", Json::option(initializer, |item| item.into())),
+                ],
+            ),
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                else_branch,
+            } => Json::node(
+                "While",
+                vec![
+                    ("condition", condition.into()),
+                    ("body", body.as_ref().into()),
+                    ("increment", Json::option(increment, |item| item.into())),
+                    (
+                        "else_branch",
+                        Json::option(else_branch, |stmt| stmt.as_ref().into()),
+                    ),
+                ],
+            ),
+            Stmt::With {
+                keyword,
+                name,
+                initializer,
+                body,
+            } => Json::node(
+                "With",
+                vec![
+                    ("keyword", keyword.into()),
+                    ("name", name.into()),
+                    ("initializer", initializer.into()),
+                    ("body", Json::array(body, |item| item.into())),
+                ],
+            ),
+        }
+    }
+}
@@ -1,22 +1,30 @@
 mod ast;
 mod callable;
+mod class;
 mod environment;
 mod interpreter;
+mod json;
+mod natives;
 mod parser;
+mod preprocessor;
+mod resolver;
 mod scanner;
 mod token;
 
 use std::error::Error;
 use std::fmt::Display;
 use std::fs::read_to_string;
-use std::io::{self, stdin, stdout, BufRead, BufReader, Write};
+use std::io::{self, stderr, stdin, stdout, BufRead, BufReader, IsTerminal, Write};
 use std::process::exit;
 
-use environment::Environment;
+use ast::Stmt;
 use interpreter::Interpreter;
+use json::Json;
 use parser::Parser;
+use preprocessor::preprocess;
+use resolver::Resolver;
 use scanner::Scanner;
-use token::{Token, TokenType};
+use token::{Literal, Token, TokenType};
 
 #[derive(Debug, Clone)]
 pub struct LoxError {
@@ -24,6 +32,8 @@ pub struct LoxError {
     col: usize,
     place: String, // where
     message: String,
+    /// The line of source the error is in, shown with a caret under the column.
+    excerpt: Option<String>,
 }
 
 impl LoxError {
@@ -33,6 +43,7 @@ impl LoxError {
             col,
             place: String::new(),
             message,
+            excerpt: None,
         }
     }
 
@@ -42,6 +53,7 @@ impl LoxError {
             col,
             place,
             message,
+            excerpt: None,
         }
     }
 
@@ -66,99 +78,480 @@ impl LoxError {
     pub(crate) fn return_unwind(keyword: &Token) -> LoxError {
         LoxError::from_token(keyword, "RETURN".to_string())
     }
-}
 
-impl Error for LoxError {}
+    pub(crate) fn is_return_unwind(&self) -> bool {
+        self.message == "RETURN"
+    }
 
-impl Display for LoxError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    pub(crate) fn break_unwind(keyword: &Token) -> LoxError {
+        LoxError::from_token(keyword, "BREAK".to_string())
+    }
+
+    pub(crate) fn is_break_unwind(&self) -> bool {
+        self.message == "BREAK"
+    }
+
+    pub(crate) fn continue_unwind(keyword: &Token) -> LoxError {
+        LoxError::from_token(keyword, "CONTINUE".to_string())
+    }
+
+    pub(crate) fn is_continue_unwind(&self) -> bool {
+        self.message == "CONTINUE"
+    }
+
+    pub(crate) fn fallthrough_unwind(keyword: &Token) -> LoxError {
+        LoxError::from_token(keyword, "FALLTHROUGH".to_string())
+    }
+
+    pub(crate) fn is_fallthrough_unwind(&self) -> bool {
+        self.message == "FALLTHROUGH"
+    }
+
+    /// Render the error for the user, optionally highlighting it with ANSI color codes.
+    ///
+    /// If the error has an excerpt, it is shown below the error with a caret pointing at the
+    /// column.
+    pub(crate) fn render(&self, color: bool) -> String {
         let Self {
             line,
             col,
             place,
             message,
+            excerpt,
         } = self;
-        write!(f, "[line {line}, col {col}] Error {place}: {message}")
+        let (red, yellow, reset) = if color {
+            (RED, YELLOW, RESET)
+        } else {
+            ("", "", "")
+        };
+        let mut rendered = format!("[line {line}, col {col}] {red}Error {place}{reset}: {message}");
+        if let Some(excerpt) = excerpt {
+            // Tabs before the column are kept, such that the caret lines up with the excerpt.
+            let indent = excerpt
+                .chars()
+                .take(col.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect::<String>();
+            rendered.push_str(&format!("\n    {excerpt}\n    {indent}{yellow}^{reset}"));
+        }
+        rendered
     }
 }
 
-fn run(source: &str) -> Result<String, LoxError> {
-    let scanner = Scanner::new(source);
-    let tokens = scanner.scan_tokens()?;
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+
+impl Error for LoxError {}
+
+impl Display for LoxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(false))
+    }
+}
+
+/// The errors collected while scanning or parsing a program.
+///
+/// At most `max` errors are kept. Any errors beyond that are only counted, so a badly broken file
+/// does not drown the user in hundreds of messages.
+#[derive(Debug, Clone)]
+pub struct LoxErrors {
+    errors: Vec<LoxError>,
+    max: usize,
+    omitted: usize,
+}
+
+impl LoxErrors {
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            errors: Vec::new(),
+            max,
+            omitted: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, error: LoxError) {
+        if self.errors.len() < self.max {
+            self.errors.push(error)
+        } else {
+            self.omitted += 1
+        }
+    }
 
-    let parser = Parser::new(tokens);
-    let parsed = parser.parse()?;
+    pub(crate) fn is_empty(&self) -> bool {
+        self.errors.is_empty() && self.omitted == 0
+    }
 
-    let mut interpreter = Interpreter::new();
-    let evaluated = interpreter.interpret(parsed)?;
+    /// Give every error the line of `source` it is in, as an excerpt to show along with it.
+    fn with_source(mut self, source: &str) -> Self {
+        let lines = source.lines().collect::<Vec<_>>();
+        for error in &mut self.errors {
+            if let Some(line) = error.line.checked_sub(1).and_then(|n| lines.get(n)) {
+                error.excerpt = Some(line.to_string());
+            }
+        }
+        self
+    }
 
-    Ok(evaluated)
+    /// Whether all errors are at the end of the input, which means it may just be incomplete.
+    fn is_at_end(&self) -> bool {
+        !self.errors.is_empty() && self.errors.iter().all(|error| error.place == "at end")
+    }
+
+    /// Render all errors for the user, optionally highlighting them with ANSI color codes.
+    pub(crate) fn render(&self, color: bool) -> String {
+        let mut rendered = self
+            .errors
+            .iter()
+            .map(|error| error.render(color))
+            .collect::<Vec<_>>()
+            .join("\n");
+        match self.omitted {
+            0 => {}
+            1 => rendered.push_str("\n... and 1 more error."),
+            omitted => rendered.push_str(&format!("\n... and {omitted} more errors.")),
+        }
+        rendered
+    }
+}
+
+impl From<LoxError> for LoxErrors {
+    fn from(error: LoxError) -> Self {
+        Self {
+            errors: vec![error],
+            max: 1,
+            omitted: 0,
+        }
+    }
+}
+
+impl Error for LoxErrors {}
+
+impl Display for LoxErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(false))
+    }
 }
 
-fn run_with_env(source: &str, environment: &mut Environment) -> Result<String, LoxError> {
-    let scanner = Scanner::new(source);
+/// The version of the interpreter, as available to scripts through `version()` and `__version__`.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The default maximum number of errors reported for a single run.
+const DEFAULT_MAX_ERRORS: usize = 20;
+
+/// Options that can be passed on the command line, regardless of the command.
+struct Options {
+    max_errors: usize,
+    /// Whether errors are highlighted with ANSI color codes.
+    color: bool,
+    sandbox: bool,
+    /// Whether statements may be terminated by a newline instead of a ';'.
+    asi: bool,
+    /// Whether assigning to an undefined variable defines a global.
+    implicit_globals: bool,
+    /// Whether lists are copied when assigned or passed, rather than shared.
+    value_lists: bool,
+    /// Whether a summary of the values allocated by a script is printed after it runs.
+    alloc_stats: bool,
+    /// The flags that `#if` directives check for.
+    flags: Vec<String>,
+    /// What the REPL prompts for a new line of input with.
+    prompt: String,
+    /// What the REPL prompts with for the next line of input that isn't complete yet.
+    continuation_prompt: String,
+    /// When set, the REPL prints the value of a line that ends in an expression, after this.
+    result_prefix: Option<String>,
+}
+
+impl Options {
+    /// Take the options out of `args`, leaving the command and its arguments behind.
+    fn take_from(args: &mut Vec<String>) -> Result<Self, String> {
+        let mut options = Self {
+            max_errors: DEFAULT_MAX_ERRORS,
+            color: stderr().is_terminal(),
+            sandbox: false,
+            asi: false,
+            implicit_globals: false,
+            value_lists: false,
+            alloc_stats: false,
+            flags: Vec::new(),
+            prompt: "> ".to_string(),
+            continuation_prompt: "... ".to_string(),
+            result_prefix: None,
+        };
+
+        let mut given = std::mem::take(args).into_iter();
+        while let Some(arg) = given.next() {
+            match arg.as_str() {
+                "--max-errors" => {
+                    options.max_errors = given
+                        .next()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|&n| n > 0)
+                        .ok_or("--max-errors expects a positive number.")?
+                }
+                "--no-color" => options.color = false,
+                "--sandbox" => options.sandbox = true,
+                "--asi" => options.asi = true,
+                "--implicit-globals" => options.implicit_globals = true,
+                "--value-lists" => options.value_lists = true,
+                "--alloc-stats" => options.alloc_stats = true,
+                "--define" => options
+                    .flags
+                    .push(given.next().ok_or("--define expects a flag name.")?),
+                "--prompt" => options.prompt = given.next().ok_or("--prompt expects a prompt.")?,
+                "--continuation-prompt" => {
+                    options.continuation_prompt = given
+                        .next()
+                        .ok_or("--continuation-prompt expects a prompt.")?
+                }
+                "--result-prefix" => {
+                    options.result_prefix =
+                        Some(given.next().ok_or("--result-prefix expects a prefix.")?)
+                }
+                _ => args.push(arg),
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Create an interpreter configured according to these options.
+    fn interpreter(&self) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_sandboxed(self.sandbox);
+        interpreter.set_implicit_globals(self.implicit_globals);
+        interpreter.set_value_lists(self.value_lists);
+        interpreter.set_alloc_stats(self.alloc_stats);
+        for flag in &self.flags {
+            interpreter.define_flag(flag);
+        }
+        interpreter
+    }
+}
+
+/// Parse the `source` read from `file`, which is only used as the value of `__file__`.
+fn parse(source: &str, file: &str, options: &Options) -> Result<Vec<Stmt>, LoxErrors> {
+    let scanner = Scanner::new(source, options.max_errors);
     let tokens = scanner.scan_tokens()?;
 
-    let parser = Parser::new(tokens);
-    let parsed = parser.parse()?;
+    let mut parser = if options.asi {
+        Parser::new_asi(tokens, options.max_errors)
+    } else {
+        Parser::new(tokens, options.max_errors)
+    };
+    parser.set_file(file);
+    parser.parse()
+}
 
-    let mut interpreter = Interpreter::new();
-    let evaluated = interpreter.interpret_with_env(parsed, environment)?;
+fn run(
+    source: &str,
+    file: &str,
+    interpreter: &mut Interpreter,
+    options: &Options,
+) -> Result<Option<Literal>, LoxErrors> {
+    let evaluated = preprocess(source, interpreter.flags(), options.max_errors)
+        .and_then(|preprocessed| parse(&preprocessed, file, options))
+        .and_then(|parsed| {
+            Resolver::new(options.max_errors).resolve(&parsed)?;
+            Ok(interpreter.interpret(parsed)?)
+        });
 
-    Ok(evaluated)
+    evaluated.map_err(|errors| errors.with_source(source))
 }
 
-fn run_file(path: &String) -> Result<(), Box<dyn Error>> {
+fn run_file(path: &String, options: &Options) -> Result<(), Box<dyn Error>> {
     let source = read_to_string(path)?;
-    run(&source)?;
+    let mut interpreter = options.interpreter();
+    let result = run(&source, path, &mut interpreter, options);
+    if let Some(stats) = interpreter.alloc_stats() {
+        eprintln!("{stats}");
+    }
+    if let Err(errors) = result {
+        eprintln!("{}", errors.render(options.color));
+        exit(65);
+    }
     Ok(())
 }
 
-fn run_prompt() -> io::Result<()> {
+/// Print the syntax tree of a script, either as text or as JSON.
+fn print_ast(path: &String, json: bool, options: &Options) -> Result<(), Box<dyn Error>> {
+    let source = read_to_string(path)?;
+    // The interpreter only provides the flags for the '#if' directives here.
+    let interpreter = options.interpreter();
+    let parsed = preprocess(&source, interpreter.flags(), options.max_errors)
+        .and_then(|source| parse(&source, path, options));
+    let statements = match parsed {
+        Ok(statements) => statements,
+        Err(errors) => {
+            eprintln!("{}", errors.with_source(&source).render(options.color));
+            exit(65);
+        }
+    };
+
+    if json {
+        println!(
+            "{}",
+            Json::Array(statements.iter().map(Json::from).collect())
+        );
+    } else {
+        for statement in statements {
+            println!("{statement}");
+        }
+    }
+    Ok(())
+}
+
+/// Run a script, and then start the REPL in the same interpreter, such that everything the script
+/// defined can be used interactively.
+fn run_interactive(path: &String, options: &Options) -> Result<(), Box<dyn Error>> {
+    let source = read_to_string(path)?;
+    let mut interpreter = options.interpreter();
+    // Unlike a plain run, an error does not end the session, so that it can be looked into.
+    if let Err(errors) = run(&source, path, &mut interpreter, options) {
+        eprintln!("{}", errors.render(options.color));
+    }
+    run_prompt(&mut interpreter, options)?;
+    Ok(())
+}
+
+fn run_prompt(interpreter: &mut Interpreter, options: &Options) -> io::Result<()> {
     let mut reader = BufReader::new(stdin().lock());
     let mut stdout = stdout().lock();
 
-    let mut env = Environment::new();
-
-    let mut line = String::new();
+    let mut source = String::new();
     loop {
-        print!("> ");
+        let prompt = if source.is_empty() {
+            &options.prompt
+        } else {
+            &options.continuation_prompt
+        };
+        print!("{prompt}");
         stdout.flush()?;
-        if reader.read_line(&mut line)? == 0 {
+        let read = reader.read_line(&mut source)?;
+        if read == 0 && source.is_empty() {
             // EOF encountered. Bye.
             break;
         }
-        match run_with_env(&line, &mut env) {
-            Ok(output) => write!(stdout, "{output}")?,
-            Err(e) => eprintln!("{e}"),
+        // An empty line gives up on completing the input, showing what is wrong with it.
+        let giving_up = read == 0 || source.ends_with("\n\n");
+        match run(&source, "<repl>", interpreter, options) {
+            Ok(value) => {
+                if let (Some(prefix), Some(value)) = (&options.result_prefix, value) {
+                    writeln!(stdout, "{}", format_repl_result(prefix, &value))?;
+                }
+            }
+            // Input that stops halfway, like a block without its '}', continues on the next line.
+            Err(e) if e.is_at_end() && !giving_up => continue,
+            Err(e) => eprintln!("{}", e.render(options.color)),
         }
-        line.clear();
+        source.clear();
     }
 
     Ok(())
 }
 
+/// Format the value of a line in the REPL, for printing after the configured prefix.
+fn format_repl_result(prefix: &str, value: &Literal) -> String {
+    format!("{prefix}{value}")
+}
+
+fn usage() -> ! {
+    eprintln!("Usage:");
+    eprintln!("\trlox [options] run [script]");
+    eprintln!("\trlox [options] batch [script] [...]");
+    eprintln!("\trlox [options] ast [--json] [script]");
+    eprintln!("\trlox [options] -i [script]");
+    eprintln!("\trlox [options]");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("\t--max-errors N\treport at most N errors (default {DEFAULT_MAX_ERRORS})");
+    eprintln!("\t--no-color\tdo not highlight errors, even on a terminal");
+    eprintln!("\t--sandbox\tdisallow access to the environment outside the interpreter");
+    eprintln!("\t--asi\t\tallow ending statements with a newline instead of a ';'");
+    eprintln!("\t--implicit-globals\tassigning to an undefined variable defines a global");
+    eprintln!(
+        "\t--value-lists\tcopy lists when they are assigned or passed, instead of sharing them"
+    );
+    eprintln!("\t--alloc-stats\tafter running a script, print how many values it allocated");
+    eprintln!("\t--define FLAG\tinclude the source between '#if FLAG' and '#endif'");
+    eprintln!("\t--prompt PROMPT\tprompt for input in the REPL with PROMPT (default '> ')");
+    eprintln!("\t--continuation-prompt PROMPT\tprompt for the rest of incomplete input with PROMPT (default '... ')");
+    eprintln!("\t--result-prefix PREFIX\tin the REPL, print the value of each line after PREFIX");
+    exit(64);
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut args = std::env::args();
-    match args.len() {
-        1 => run_prompt()?,
-        _ => match args.nth(1).unwrap().as_str() {
-            "run" => run_file(&args.next().unwrap())?,
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let options = match Options::take_from(&mut args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}");
+            usage();
+        }
+    };
+
+    let mut args = args.into_iter();
+    match args.next() {
+        None => run_prompt(&mut options.interpreter(), &options)?,
+        Some(command) => match command.as_str() {
+            "run" => run_file(&args.next().unwrap_or_else(|| usage()), &options)?,
+            "-i" => run_interactive(&args.next().unwrap_or_else(|| usage()), &options)?,
+            "ast" => {
+                let mut args = args.collect::<Vec<_>>();
+                let json = args.first().is_some_and(|arg| arg == "--json");
+                if json {
+                    args.remove(0);
+                }
+                print_ast(&args.pop().unwrap_or_else(|| usage()), json, &options)?
+            }
             "batch" => {
                 for file in args.collect::<Vec<_>>() {
                     eprintln!("\nRunning '{file}'...");
-                    run_file(&file)?
+                    run_file(&file, &options)?
                 }
             }
-            _ => {
-                eprintln!("Usage:");
-                eprintln!("\trlox run [script]");
-                eprintln!("\trlox batch [script] [...]");
-                eprintln!("\trlox");
-                exit(64);
-            }
+            _ => usage(),
         },
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_highlights_only_with_color() {
+        let error = LoxError {
+            line: 2,
+            col: 9,
+            place: "at '+'".to_string(),
+            message: "Operands must be numbers.".to_string(),
+            excerpt: Some("\tprint a + nil;".to_string()),
+        };
+
+        let plain = error.render(false);
+        assert!(!plain.contains('\x1b'));
+        assert_eq!(
+            plain,
+            "[line 2, col 9] Error at '+': Operands must be numbers.\n\
+             \x20   \tprint a + nil;\n\
+             \x20   \t       ^"
+        );
+
+        let colored = error.render(true);
+        assert!(colored.contains(&format!("{RED}Error at '+'{RESET}")));
+        assert!(colored.ends_with(&format!("\t       {YELLOW}^{RESET}")));
+    }
+
+    #[test]
+    fn repl_results_follow_the_prefix() {
+        let number = Literal::Number(3.0);
+        assert_eq!(format_repl_result("", &number), "3");
+        assert_eq!(format_repl_result("=> ", &number), "=> 3");
+        let string = Literal::String("three".to_string());
+        assert_eq!(format_repl_result("=> ", &string), "=> three");
+    }
+}
@@ -0,0 +1,1029 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{stdout, BufRead, Write};
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::callable::{NativeFn, NativeFunction};
+use crate::class::{Class, Instance};
+use crate::environment::Environment;
+use crate::interpreter::{Allocation, Interpreter, Sink};
+use crate::token::{Literal, Number, Token, TokenType};
+use crate::{LoxError, VERSION};
+
+/// Define all native functions in the global environment of the `interpreter`.
+pub(crate) fn define_natives(interpreter: &mut Interpreter) {
+    interpreter.define_native("toBase", 2, to_base);
+    interpreter.define_native("fromBase", 2, from_base);
+    interpreter.define_optional_native("parseInt", 2, 1, parse_int);
+    interpreter.define_native("parseFloat", 1, parse_float);
+    interpreter.define_native("getenv", 1, getenv);
+    interpreter.define_native("readAll", 0, read_all);
+    interpreter.define_native("lines", 0, lines);
+    interpreter.define_native("input", 0, input);
+    interpreter.define_native("isNaN", 1, is_nan);
+    interpreter.define_native("isInfinite", 1, is_infinite);
+    interpreter.define_native("isFinite", 1, is_finite);
+    interpreter.define_native("idiv", 2, idiv);
+    interpreter.define_native("time", 1, time);
+    interpreter.define_native("withTimeout", 2, with_timeout);
+    interpreter.define_native("assertEq", 2, assert_eq);
+    interpreter.define_native("assertThrows", 1, assert_throws);
+    interpreter.define_native("captureOutput", 1, capture_output);
+    interpreter.define_native("hash", 1, hash);
+    interpreter.define_native("bind", 2, bind);
+    interpreter.register_native("deepEqual", 2, Box::new(deep_equal));
+    // These only need their arguments, so they are registered like any function from outside.
+    interpreter.register_native("returnedExplicitly", 0, Box::new(returned_explicitly));
+    interpreter.register_native("traceOn", 0, Box::new(trace_on));
+    interpreter.register_native("traceOff", 0, Box::new(trace_off));
+    interpreter.register_native("version", 0, Box::new(version));
+    interpreter.register_native("repr", 1, Box::new(repr));
+    interpreter.define_optional_native("sort", 2, 1, sort);
+    interpreter.define_native("sum", 1, sum);
+    interpreter.define_native("minOf", 1, min_of);
+    interpreter.define_native("maxOf", 1, max_of);
+    interpreter.define_native("Cache", 0, cache);
+    interpreter.define_variadic_native("format", 1, format);
+    interpreter.define_variadic_native("printf", 1, printf);
+    for &(name, arity, function) in LIST_METHODS.iter().chain(STRING_METHODS) {
+        interpreter.define_native(name, arity, function);
+    }
+}
+
+/// The natives that can also be called as a method of a list, which is then the first argument.
+const LIST_METHODS: &[(&str, usize, NativeFn)] = &[
+    ("indexOf", 2, index_of),
+    ("contains", 2, contains),
+    ("length", 1, length),
+];
+
+/// Like [`LIST_METHODS`], the natives that can also be called as a method of a string.
+const STRING_METHODS: &[(&str, usize, NativeFn)] = &[("split", 2, split), ("length", 1, length)];
+
+/// The methods of a cache, which are bound to the map that backs it.
+const CACHE_METHODS: &[(&str, usize, NativeFn)] = &[
+    ("get", 2, cache_get),
+    ("set", 3, cache_set),
+    ("has", 2, cache_has),
+];
+
+/// Get the native that is called as the method `name` of the list or string `receiver`, not bound
+/// to it yet.
+pub(crate) fn method(receiver: &Literal, name: &str) -> Option<NativeFunction> {
+    let methods = match receiver {
+        Literal::List(_) => LIST_METHODS,
+        Literal::String(_) => STRING_METHODS,
+        _ => return None,
+    };
+    methods
+        .iter()
+        .find(|(method, ..)| *method == name)
+        .map(|&(name, arity, function)| NativeFunction::new(name, arity, function))
+}
+
+/// Return an error if the interpreter is sandboxed, for natives that reach outside of it.
+fn check_sandbox(interpreter: &Interpreter, paren: &Token, name: &str) -> Result<(), LoxError> {
+    if interpreter.sandboxed() {
+        return Err(LoxError::from_token(
+            paren,
+            format!("Can't use '{name}' in sandbox mode."),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Get a number argument, or an error saying that the native `name` expects one.
+fn number(paren: &Token, argument: &Literal, name: &str) -> Result<Number, LoxError> {
+    argument
+        .number()
+        .ok_or_else(|| LoxError::from_token(paren, format!("{name} expects a number.")))
+}
+
+/// Get the radix from a base argument, which must be a whole number between 2 and 36.
+fn radix(paren: &Token, base: &Literal) -> Result<u32, LoxError> {
+    match base {
+        Literal::Number(n) if n.fract() == 0.0 && (2.0..=36.0).contains(n) => Ok(*n as u32),
+        _ => Err(LoxError::from_token(
+            paren,
+            "Base must be a whole number between 2 and 36.".to_string(),
+        )),
+    }
+}
+
+/// toBase(n, base) returns the string representation of the integer `n` in the given `base`.
+fn to_base(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let radix = radix(paren, &arguments[1])?;
+    let n = match arguments[0] {
+        // Unlike i64::MAX, i64::MIN is a power of two and so exactly a Number. The range is
+        // exclusive because i64::MAX as a Number rounds up to one past it.
+        Literal::Number(n)
+            if n.fract() == 0.0 && (i64::MIN as Number..-(i64::MIN as Number)).contains(&n) =>
+        {
+            n as i64
+        }
+        _ => {
+            return Err(LoxError::from_token(
+                paren,
+                "Can only convert integers to another base.".to_string(),
+            ))
+        }
+    };
+
+    let mut magnitude = n.unsigned_abs();
+    let mut digits = Vec::new();
+    loop {
+        // The digit is always smaller than the radix, so this will never fail.
+        digits.push(char::from_digit((magnitude % radix as u64) as u32, radix).unwrap());
+        magnitude /= radix as u64;
+        if magnitude == 0 {
+            break;
+        }
+    }
+    if n < 0 {
+        digits.push('-');
+    }
+
+    Ok(Literal::String(digits.iter().rev().collect()))
+}
+
+/// fromBase(s, base) parses the string `s` as an integer in the given `base`.
+fn from_base(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let radix = radix(paren, &arguments[1])?;
+    let s = arguments[0].string().ok_or_else(|| {
+        LoxError::from_token(paren, "Can only parse strings from a base.".to_string())
+    })?;
+
+    i64::from_str_radix(s, radix)
+        .map(|n| Literal::Number(n as Number))
+        .map_err(|_| LoxError::from_token(paren, format!("Invalid base {radix} number '{s}'.")))
+}
+
+/// parseInt(s, base) parses the integer at the start of the string `s` in the given `base`, which
+/// is 10 if left out. Leading whitespace and anything after the digits is ignored. In base 16, a
+/// `0x` prefix is allowed.
+fn parse_int(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let radix = match arguments.get(1) {
+        Some(base) => radix(paren, base)?,
+        None => 10,
+    };
+    let s = arguments[0]
+        .string()
+        .ok_or_else(|| LoxError::from_token(paren, "parseInt expects a string.".to_string()))?;
+
+    let trimmed = s.trim_start();
+    let (sign, rest) = match trimmed.strip_prefix('-') {
+        Some(rest) => (-1.0, rest),
+        None => (1.0, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let rest = match radix {
+        16 => rest
+            .strip_prefix("0x")
+            .or_else(|| rest.strip_prefix("0X"))
+            .unwrap_or(rest),
+        _ => rest,
+    };
+
+    let digits = rest
+        .chars()
+        .map_while(|c| c.to_digit(radix))
+        .collect::<Vec<_>>();
+    if digits.is_empty() {
+        return Err(LoxError::from_token(
+            paren,
+            format!("Can't parse a base {radix} integer from '{s}'."),
+        ));
+    }
+    let n = digits
+        .iter()
+        .fold(0.0, |n, &digit| n * radix as Number + digit as Number);
+
+    Ok(Literal::Number(sign * n))
+}
+
+/// parseFloat(s) parses the number at the start of the string `s`, ignoring leading whitespace and
+/// anything after the number. The number may have a fractional part and an exponent.
+fn parse_float(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let s = arguments[0]
+        .string()
+        .ok_or_else(|| LoxError::from_token(paren, "parseFloat expects a string.".to_string()))?;
+
+    let trimmed = s.trim_start();
+    let bytes = trimmed.as_bytes();
+    let digits_from = |start: usize| {
+        start
+            + bytes[start..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit())
+                .count()
+    };
+
+    // Find the end of the longest prefix that is a number.
+    let mut end = match bytes.first() {
+        Some(b'+' | b'-') => 1,
+        _ => 0,
+    };
+    let mantissa_start = end;
+    end = digits_from(end);
+    let mut digit_count = end - mantissa_start;
+    if bytes.get(end) == Some(&b'.') {
+        let fraction_end = digits_from(end + 1);
+        digit_count += fraction_end - (end + 1);
+        end = fraction_end;
+    }
+    if digit_count == 0 {
+        return Err(LoxError::from_token(
+            paren,
+            format!("Can't parse a number from '{s}'."),
+        ));
+    }
+    if let Some(b'e' | b'E') = bytes.get(end) {
+        let exponent_start = match bytes.get(end + 1) {
+            Some(b'+' | b'-') => end + 2,
+            _ => end + 1,
+        };
+        let exponent_end = digits_from(exponent_start);
+        // Without digits, the 'e' is not part of the number.
+        if exponent_end > exponent_start {
+            end = exponent_end;
+        }
+    }
+
+    // The prefix consists of the parts of a number, so it always parses.
+    Ok(Literal::Number(trimmed[..end].parse().unwrap()))
+}
+
+/// getenv(name) returns the value of the environment variable `name`, or nil if it is not set.
+fn getenv(
+    interpreter: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    check_sandbox(interpreter, paren, "getenv")?;
+    let name = arguments[0].string().ok_or_else(|| {
+        LoxError::from_token(
+            paren,
+            "Environment variable name must be a string.".to_string(),
+        )
+    })?;
+
+    Ok(match std::env::var(name) {
+        Ok(value) => Literal::String(value),
+        Err(_) => Literal::Nil,
+    })
+}
+
+/// readAll() returns everything that is left on standard input as a single string.
+fn read_all(
+    interpreter: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    _: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    check_sandbox(interpreter, paren, "readAll")?;
+    let mut input = String::new();
+    interpreter
+        .with_input(|reader| reader.read_to_string(&mut input))
+        .map_err(|e| LoxError::from_token(paren, format!("Could not read standard input: {e}.")))?;
+
+    Ok(Literal::String(input))
+}
+
+/// lines() returns the lines that are left on standard input as a list of strings, without their
+/// line endings.
+fn lines(
+    interpreter: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    _: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    check_sandbox(interpreter, paren, "lines")?;
+    let lines = interpreter
+        .with_input(|reader| BufRead::lines(reader).collect::<Result<Vec<_>, _>>())
+        .map_err(|e| LoxError::from_token(paren, format!("Could not read standard input: {e}.")))?;
+
+    interpreter.count_allocation(Allocation::List);
+    let lines = lines.into_iter().map(Literal::String).collect();
+    Ok(Literal::List(Rc::new(RefCell::new(lines))))
+}
+
+/// input() returns the next line of standard input, without its line ending, or nil when there
+/// is no more input.
+fn input(
+    interpreter: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    _: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    check_sandbox(interpreter, paren, "input")?;
+    let mut line = String::new();
+    let read = interpreter
+        .with_input(|reader| reader.read_line(&mut line))
+        .map_err(|e| LoxError::from_token(paren, format!("Could not read standard input: {e}.")))?;
+    if read == 0 {
+        return Ok(Literal::Nil);
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+
+    Ok(Literal::String(line))
+}
+
+/// isNaN(x) returns whether `x` is NaN, such as the result of `0 / 0`.
+fn is_nan(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    Ok(Literal::Bool(
+        number(paren, &arguments[0], "isNaN")?.is_nan(),
+    ))
+}
+
+/// isInfinite(x) returns whether `x` is positive or negative infinity, such as the result of
+/// `1 / 0`.
+fn is_infinite(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    Ok(Literal::Bool(
+        number(paren, &arguments[0], "isInfinite")?.is_infinite(),
+    ))
+}
+
+/// isFinite(x) returns whether `x` is neither infinite nor NaN.
+fn is_finite(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    Ok(Literal::Bool(
+        number(paren, &arguments[0], "isFinite")?.is_finite(),
+    ))
+}
+
+/// idiv(a, b) divides `a` by `b`, rounding down to the nearest integer like Python's `//`, which
+/// Lox can't have as an operator because it starts a comment.
+fn idiv(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let a = number(paren, &arguments[0], "idiv")?;
+    let b = number(paren, &arguments[1], "idiv")?;
+    if b == 0.0 {
+        return Err(LoxError::from_token(paren, "Division by zero.".to_string()));
+    }
+
+    Ok(Literal::Number((a / b).floor()))
+}
+
+/// time(f) calls the function `f` without arguments and returns how many seconds the call took.
+/// The result of `f` itself is discarded.
+fn time(
+    interpreter: &mut Interpreter,
+    environment: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let start = Instant::now();
+    interpreter.call(&arguments[0], environment, paren, Vec::new())?;
+
+    Ok(Literal::Number(start.elapsed().as_secs_f64() as Number))
+}
+
+/// withTimeout(millis, f) calls the function `f` without arguments and returns its result, but
+/// fails if the call takes longer than `millis` milliseconds.
+fn with_timeout(
+    interpreter: &mut Interpreter,
+    environment: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let millis = number(paren, &arguments[0], "withTimeout")?;
+    if !(millis >= 0.0 && millis.is_finite()) {
+        return Err(LoxError::from_token(
+            paren,
+            "Timeout must be a non-negative number of milliseconds.".to_string(),
+        ));
+    }
+
+    interpreter.with_timeout(millis, paren, |interpreter| {
+        interpreter.call(&arguments[1], environment, paren, Vec::new())
+    })
+}
+
+/// assertEq(actual, expected) fails unless `actual` and `expected` are equal. For two strings,
+/// the failure points out where they start to differ.
+fn assert_eq(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let (actual, expected) = (&arguments[0], &arguments[1]);
+    if Literal::is_equal(actual.clone(), expected.clone()).is_truthy() {
+        return Ok(Literal::Nil);
+    }
+
+    let message = match (actual.string(), expected.string()) {
+        (Some(actual), Some(expected)) => string_difference(actual, expected),
+        _ => format!("{actual} != {expected}"),
+    };
+    Err(LoxError::from_token(
+        paren,
+        format!("Assertion failed: {message}."),
+    ))
+}
+
+/// Describe where the strings `left` and `right` first differ, showing each around that index.
+fn string_difference(left: &str, right: &str) -> String {
+    /// The number of characters shown on either side of the first difference.
+    const CONTEXT: usize = 10;
+
+    let left = left.chars().collect::<Vec<_>>();
+    let right = right.chars().collect::<Vec<_>>();
+    let index = left.iter().zip(&right).take_while(|(l, r)| l == r).count();
+    let excerpt = |chars: &[char]| {
+        let start = index.saturating_sub(CONTEXT);
+        let end = chars.len().min(index + CONTEXT);
+        let mut excerpt = chars[start..end].iter().collect::<String>();
+        if start > 0 {
+            excerpt.insert(0, '…');
+        }
+        if end < chars.len() {
+            excerpt.push('…');
+        }
+        excerpt
+    };
+
+    format!(
+        "strings differ at index {index}: \"{}\" != \"{}\"",
+        excerpt(&left),
+        excerpt(&right)
+    )
+}
+
+/// assertThrows(f) calls the function `f` without arguments, and fails unless that results in an
+/// error. Useful for testing that something is rejected.
+fn assert_throws(
+    interpreter: &mut Interpreter,
+    environment: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    if arguments[0].callable().is_none() {
+        return Err(LoxError::from_token(
+            paren,
+            "assertThrows expects a function.".to_string(),
+        ));
+    }
+
+    match interpreter.call(&arguments[0], environment, paren, Vec::new()) {
+        Ok(_) => Err(LoxError::from_token(
+            paren,
+            "Expected an error but none was thrown.".to_string(),
+        )),
+        Err(_) => Ok(Literal::Nil),
+    }
+}
+
+/// captureOutput(f) calls the function `f` without arguments, and returns everything it printed
+/// as a string instead of writing it to stdout.
+fn capture_output(
+    interpreter: &mut Interpreter,
+    environment: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    if arguments[0].callable().is_none() {
+        return Err(LoxError::from_token(
+            paren,
+            "captureOutput expects a function.".to_string(),
+        ));
+    }
+
+    let output = interpreter.capture_output(Sink::Stdout, |interpreter| {
+        interpreter.call(&arguments[0], environment, paren, Vec::new())
+    })?;
+    Ok(Literal::String(output))
+}
+
+/// returnedExplicitly() tells whether the last function that was called ended in a return
+/// statement, which tells `return nil;` apart from running off the end of the body.
+fn returned_explicitly(interpreter: &mut Interpreter, _: &[Literal]) -> Result<Literal, LoxError> {
+    Ok(Literal::Bool(interpreter.returned_explicitly()))
+}
+
+/// traceOn() starts tracing every call to a Lox function to stderr, with its arguments and the
+/// value it returns, until traceOff() is called.
+fn trace_on(interpreter: &mut Interpreter, _: &[Literal]) -> Result<Literal, LoxError> {
+    interpreter.set_tracing(true);
+    Ok(Literal::Nil)
+}
+
+fn trace_off(interpreter: &mut Interpreter, _: &[Literal]) -> Result<Literal, LoxError> {
+    interpreter.set_tracing(false);
+    Ok(Literal::Nil)
+}
+
+/// version() returns the version of the interpreter, like "0.1.0".
+fn version(_: &mut Interpreter, _: &[Literal]) -> Result<Literal, LoxError> {
+    Ok(Literal::String(VERSION.to_string()))
+}
+
+/// hash(x) returns a hash of the number, string, boolean or nil `x`, as a whole number. Equal
+/// values hash the same, and the hash of a value is the same in every run.
+fn hash(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    // Values of different types are told apart by a tag in front of their contents.
+    let bytes = match &arguments[0] {
+        Literal::Nil => vec![0],
+        Literal::Bool(b) => vec![1, *b as u8],
+        // Adding zero turns -0 into 0, which is equal to it.
+        Literal::Number(n) => [&[2], &(n + 0.0).to_le_bytes()[..]].concat(),
+        Literal::String(s) => [&[3], s.as_bytes()].concat(),
+        _ => {
+            return Err(LoxError::from_token(
+                paren,
+                "Can only hash numbers, strings, booleans and nil.".to_string(),
+            ))
+        }
+    };
+
+    // The 32-bit FNV-1a hash, rather than Rust's own hasher, whose results are not stable.
+    let hash = bytes.iter().fold(0x811c9dc5_u32, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    });
+    Ok(Literal::Number(hash as Number))
+}
+
+/// repr(x) returns the default representation of `x`, like `<Point instance>` for an instance,
+/// even if its class has a `toString` method that `print` would use instead.
+fn repr(_: &mut Interpreter, arguments: &[Literal]) -> Result<Literal, LoxError> {
+    Ok(Literal::String(arguments[0].to_string()))
+}
+
+/// deepEqual(a, b) tells whether `a` and `b` have the same structure: lists and maps with deeply
+/// equal contents, or instances of the same class with deeply equal fields. Unlike `==`, this
+/// looks into instances rather than only telling whether they are the same one.
+fn deep_equal(_: &mut Interpreter, arguments: &[Literal]) -> Result<Literal, LoxError> {
+    Ok(Literal::Bool(deeply_equal(&arguments[0], &arguments[1])))
+}
+
+fn deeply_equal(left: &Literal, right: &Literal) -> bool {
+    match (left, right) {
+        (Literal::List(a), Literal::List(b)) => {
+            let (a, b) = (a.borrow(), b.borrow());
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| deeply_equal(a, b))
+        }
+        (Literal::Map(a), Literal::Map(b)) => {
+            let (a, b) = (a.borrow(), b.borrow());
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| deeply_equal(a, b)))
+        }
+        (Literal::Instance(a), Literal::Instance(b)) => {
+            let (a, b) = (a.borrow(), b.borrow());
+            std::ptr::eq(a.class(), b.class())
+                && a.fields().len() == b.fields().len()
+                && a.fields()
+                    .iter()
+                    .all(|(name, a)| b.fields().get(name).is_some_and(|b| deeply_equal(a, b)))
+        }
+        _ => Literal::is_equal(left.clone(), right.clone()).is_truthy(),
+    }
+}
+
+/// bind(method, instance) returns the Lox function `method` with `this` bound to `instance`,
+/// however it is called afterwards.
+fn bind(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let Literal::Fun(function) = &arguments[0] else {
+        return Err(LoxError::from_token(
+            paren,
+            "bind expects a function.".to_string(),
+        ));
+    };
+    if !matches!(arguments[1], Literal::Instance(_)) {
+        return Err(LoxError::from_token(
+            paren,
+            "Can only bind a function to an instance.".to_string(),
+        ));
+    }
+
+    Ok(Literal::Fun(Box::new(function.bind(arguments[1].clone()))))
+}
+
+/// Get the elements of a list argument, or an error saying that the native `name` expects one.
+fn list(paren: &Token, argument: &Literal, name: &str) -> Result<Vec<Literal>, LoxError> {
+    match argument {
+        Literal::List(elements) => Ok(elements.borrow().clone()),
+        _ => Err(LoxError::from_token(
+            paren,
+            format!("{name} expects a list."),
+        )),
+    }
+}
+
+/// indexOf(list, value) returns the index of the first element of `list` that is equal to
+/// `value`, or -1 if there is none.
+fn index_of(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let index = list(paren, &arguments[0], "indexOf")?
+        .into_iter()
+        .position(|element| Literal::is_equal(element, arguments[1].clone()).is_truthy());
+
+    Ok(Literal::Number(index.map_or(-1.0, |index| index as Number)))
+}
+
+/// contains(list, value) tells whether any element of `list` is equal to `value`.
+fn contains(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let found = list(paren, &arguments[0], "contains")?
+        .into_iter()
+        .any(|element| Literal::is_equal(element, arguments[1].clone()).is_truthy());
+
+    Ok(Literal::Bool(found))
+}
+
+/// length(x) returns the number of elements of the list `x`, or the number of characters of the
+/// string `x`.
+fn length(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let length = match &arguments[0] {
+        Literal::List(elements) => elements.borrow().len(),
+        Literal::String(s) => s.chars().count(),
+        _ => {
+            return Err(LoxError::from_token(
+                paren,
+                "length expects a list or a string.".to_string(),
+            ))
+        }
+    };
+
+    Ok(Literal::Number(length as Number))
+}
+
+/// split(s, separator) returns the list of the parts of the string `s` between occurrences of
+/// `separator`.
+fn split(
+    interpreter: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let (Some(s), Some(separator)) = (arguments[0].string(), arguments[1].string()) else {
+        return Err(LoxError::from_token(
+            paren,
+            "split expects two strings.".to_string(),
+        ));
+    };
+    if separator.is_empty() {
+        return Err(LoxError::from_token(
+            paren,
+            "Can't split on an empty separator.".to_string(),
+        ));
+    }
+
+    interpreter.count_allocation(Allocation::List);
+    let parts = s
+        .split(separator.as_str())
+        .map(|part| Literal::String(part.to_string()))
+        .collect();
+    Ok(Literal::List(Rc::new(RefCell::new(parts))))
+}
+
+/// sort(list, compare) sorts `list` in place. Without the function `compare`, the list must
+/// consist of only numbers or only strings, which are sorted in ascending order. Otherwise,
+/// `compare(a, b)` returns a negative number if `a` goes before `b`, a positive number if it goes
+/// after, and zero if their order doesn't matter.
+fn sort(
+    interpreter: &mut Interpreter,
+    environment: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let Literal::List(list) = &arguments[0] else {
+        return Err(LoxError::from_token(
+            paren,
+            "sort expects a list.".to_string(),
+        ));
+    };
+    // Sorted on the side, as the comparison may look at the list while it is being sorted.
+    let mut elements = list.borrow().clone();
+
+    match arguments.get(1) {
+        Some(compare) => {
+            // The first error stops the comparisons that would otherwise follow it.
+            let mut error = None;
+            elements.sort_by(|a, b| {
+                if error.is_some() {
+                    return Ordering::Equal;
+                }
+                let order = interpreter
+                    .call(compare, environment, paren, vec![a.clone(), b.clone()])
+                    .and_then(|order| {
+                        order.number().ok_or_else(|| {
+                            LoxError::from_token(
+                                paren,
+                                "Comparison must return a number.".to_string(),
+                            )
+                        })
+                    });
+                match order {
+                    Ok(order) => order.partial_cmp(&0.0).unwrap_or(Ordering::Equal),
+                    Err(e) => {
+                        error = Some(e);
+                        Ordering::Equal
+                    }
+                }
+            });
+            if let Some(error) = error {
+                return Err(error);
+            }
+        }
+        None if elements.iter().all(|element| element.number().is_some()) => {
+            elements.sort_by(|a, b| {
+                a.number()
+                    .partial_cmp(&b.number())
+                    .unwrap_or(Ordering::Equal)
+            });
+        }
+        None if elements.iter().all(|element| element.string().is_some()) => {
+            elements.sort_by(|a, b| a.string().cmp(&b.string()));
+        }
+        None => {
+            return Err(LoxError::from_token(
+                paren,
+                "Can only sort a list of only numbers or only strings without a comparison."
+                    .to_string(),
+            ))
+        }
+    }
+
+    *list.borrow_mut() = elements;
+    Ok(Literal::Nil)
+}
+
+/// Get the elements of a list argument that must all be numbers, and of which there must be at
+/// least one, or an error saying that the native `name` expects such a list.
+fn numbers(paren: &Token, argument: &Literal, name: &str) -> Result<Vec<Number>, LoxError> {
+    let numbers = list(paren, argument, name)?
+        .iter()
+        .map(Literal::number)
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| LoxError::from_token(paren, format!("{name} expects a list of numbers.")))?;
+    if numbers.is_empty() {
+        return Err(LoxError::from_token(
+            paren,
+            format!("{name} expects a list that is not empty."),
+        ));
+    }
+
+    Ok(numbers)
+}
+
+/// sum(list) returns the sum of the numbers in `list`.
+fn sum(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let numbers = numbers(paren, &arguments[0], "sum")?;
+    Ok(Literal::Number(numbers.into_iter().sum()))
+}
+
+/// minOf(list) returns the smallest of the numbers in `list`.
+fn min_of(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let numbers = numbers(paren, &arguments[0], "minOf")?;
+    Ok(Literal::Number(
+        numbers.into_iter().fold(Number::INFINITY, Number::min),
+    ))
+}
+
+/// maxOf(list) returns the largest of the numbers in `list`.
+fn max_of(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let numbers = numbers(paren, &arguments[0], "maxOf")?;
+    Ok(Literal::Number(
+        numbers.into_iter().fold(Number::NEG_INFINITY, Number::max),
+    ))
+}
+
+/// Substitute the `arguments` for the `{}` placeholders in the format string `fmt`, in order.
+/// Literal braces are written as `{{` and `}}`.
+fn format_string(paren: &Token, fmt: &Literal, arguments: &[Literal]) -> Result<String, LoxError> {
+    let fmt = fmt
+        .string()
+        .ok_or_else(|| LoxError::from_token(paren, "Format must be a string.".to_string()))?;
+
+    let given = arguments.len();
+    let mut formatted = String::new();
+    let mut arguments = arguments.iter();
+    let mut placeholders = 0;
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                formatted.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                placeholders += 1;
+                if let Some(argument) = arguments.next() {
+                    formatted.push_str(&argument.to_string());
+                }
+            }
+            ('{', _) | ('}', _) => {
+                return Err(LoxError::from_token(
+                    paren,
+                    "Unmatched brace in format string. Use '{{' or '}}' for a literal brace."
+                        .to_string(),
+                ))
+            }
+            _ => formatted.push(c),
+        }
+    }
+
+    if placeholders != given {
+        return Err(LoxError::from_token(
+            paren,
+            format!("Format string has {placeholders} placeholders but got {given} arguments."),
+        ));
+    }
+
+    Ok(formatted)
+}
+
+/// format(fmt, ...) returns the format string `fmt` with its `{}` placeholders replaced by the
+/// remaining arguments.
+fn format(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    Ok(Literal::String(format_string(
+        paren,
+        &arguments[0],
+        &arguments[1..],
+    )?))
+}
+
+/// printf(fmt, ...) prints the result of `format(fmt, ...)`, without a trailing newline.
+fn printf(
+    interpreter: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    interpreter.write_output(&format_string(paren, &arguments[0], &arguments[1..])?);
+    // Without a newline, the output would otherwise linger in the buffer.
+    let _ = stdout().flush();
+    Ok(Literal::Nil)
+}
+
+/// Cache() returns an instance of the class `Cache`, backed by a map. Its `get(key)`,
+/// `set(key, value)` and `has(key)` methods read and write that map, and getting a key that isn't
+/// cached returns nil.
+fn cache(
+    interpreter: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    _: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let name = Token::new(
+        TokenType::Identifier,
+        "Cache".to_string(),
+        None,
+        paren.line(),
+        paren.col(),
+    );
+    let class = Class::new(name, None, HashMap::new(), HashMap::new());
+    let entries = Literal::Map(Rc::new(RefCell::new(HashMap::new())));
+    interpreter.count_allocation(Allocation::Map);
+    let mut instance = Instance::new(Rc::new(class));
+    for &(name, arity, function) in CACHE_METHODS {
+        let method = NativeFunction::new(name, arity, function).bind(entries.clone());
+        instance.set(name, Literal::Native(method));
+    }
+    interpreter.count_allocation(Allocation::Instance);
+    Ok(Literal::Instance(Rc::new(RefCell::new(instance))))
+}
+
+/// The map backing a cache.
+type CacheEntries = RefCell<HashMap<String, Literal>>;
+
+/// Get the map backing a cache, and the key a method of it was called with, which must be a
+/// string like the keys of any map.
+fn cache_entry<'a>(
+    paren: &Token,
+    arguments: &'a [Literal],
+) -> Result<(&'a CacheEntries, &'a String), LoxError> {
+    match (&arguments[0], &arguments[1]) {
+        (Literal::Map(entries), Literal::String(key)) => Ok((entries, key)),
+        _ => Err(LoxError::from_token(
+            paren,
+            "Cache keys must be strings.".to_string(),
+        )),
+    }
+}
+
+fn cache_get(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let (entries, key) = cache_entry(paren, &arguments)?;
+    let value = entries.borrow().get(key).cloned();
+    Ok(value.unwrap_or(Literal::Nil))
+}
+
+fn cache_set(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let (entries, key) = cache_entry(paren, &arguments)?;
+    entries
+        .borrow_mut()
+        .insert(key.clone(), arguments[2].clone());
+    Ok(arguments[2].clone())
+}
+
+fn cache_has(
+    _: &mut Interpreter,
+    _: &Environment,
+    paren: &Token,
+    arguments: Vec<Literal>,
+) -> Result<Literal, LoxError> {
+    let (entries, key) = cache_entry(paren, &arguments)?;
+    let has = entries.borrow().contains_key(key);
+    Ok(Literal::Bool(has))
+}
@@ -1,7 +1,7 @@
-use crate::ast::{Expr, Stmt};
+use crate::ast::{Depth, Expr, MapEntry, MatchArm, Stmt, SwitchCase, WrappedStmt};
 use crate::token::TokenType::{self, *};
-use crate::token::{Literal, Token};
-use crate::LoxError;
+use crate::token::{Literal, Number, Token};
+use crate::{LoxError, LoxErrors};
 
 type ReturnOrError = Result<Stmt, LoxError>;
 
@@ -12,66 +12,161 @@ type ReturnOrError = Result<Stmt, LoxError>;
 /// ```
 /// program        → declaration* EOF ;
 ///
-/// declaration    → funDecl
+/// declaration    → classDecl
+///                | enumDecl
+///                | funDecl
 ///                | varDecl
 ///                | statement ;
 ///
 /// statement      → exprStmt
+///                | assertStmt
+///                | breakStmt
+///                | continueStmt
+///                | fallthroughStmt
 ///                | forStmt
 ///                | ifStmt
+///                | unlessStmt
+///                | loopStmt
 ///                | printStmt
+///                | eprintStmt
 ///                | returnStmt
+///                | switchStmt
 ///                | whileStmt
+///                | withStmt
 ///                | block ;
 ///
+/// classDecl      → "class" IDENTIFIER ( "<" IDENTIFIER )?
+///                  "{" ( function | staticField )* "}" ;
+/// staticField    → "static" IDENTIFIER "=" expression ";" ;
+/// enumDecl       → "enum" IDENTIFIER "{" ( IDENTIFIER ( "," IDENTIFIER )* ","? )? "}" ;
 /// funDecl        → "fun" function ;
 /// function       → IDENTIFIER "(" parameters? ")" block ;
 /// parameters     → IDENTIFIER ( "," IDENTIFIER )* ;
 ///
 /// forStmt        → "for" "(" ( varDecl | exprStmt | ";" )
 ///                  expression? ";"
-///                  expression? ")" statement ;
+///                  expression? ")" loopBody ;
+///
+/// breakStmt      → "break" expression? ";" ;
+///
+/// continueStmt   → "continue" ";" ;
+///
+/// fallthroughStmt → "fallthrough" ";" ;
 ///
 /// returnStmt     → "return" expression? ";" ;
 ///
-/// whileStmt      → "while" "(" expression ")" statement ;
+/// assertStmt     → "assert" expression ( "," expression )? ";" ;
+///
+/// whileStmt      → "while" "(" ( "var" IDENTIFIER "=" )? expression ")" loopBody ;
+///
+/// loopBody       → block ( "else" statement )?
+///                | statement ;
+///
+/// loopStmt       → loop ";"? ;
+///
+/// switchStmt     → switch ";"? ;
 ///
 /// ifStmt         → "if" "(" expression ")" statement
 ///                ( "else" statement )? ;
 ///
-/// block          → "{" declaration* "}" ;
+/// unlessStmt     → "unless" "(" expression ")" statement ;
+///
+/// withStmt       → "with" "(" "var" IDENTIFIER "=" expression ")" block ;
+///
+/// block          → "{" declaration* expression? "}" ;
 ///
 /// varDecl        → "var" IDENTIFIER ( "=" expression )? ";" ;
 ///
 /// exprStmt       → expression ";" ;
 /// printStmt      → "print" expression ";" ;
+/// eprintStmt     → "eprint" expression ";" ;
 ///
 /// expression     → assignment ;
-/// assignment     → IDENTIFIER "=" assignment
-///                | logic_or ;
+/// assignment     → ( call "." )? IDENTIFIER "=" assignment
+///                | call "[" expression "]" "=" assignment
+///                | conditional ;
+/// conditional    → logic_or ( "?" expression ":" conditional )? ;
 /// logic_or       → logic_and ( "or" logic_and )* ;
 /// logic_and      → equality ( "and" equality )* ;
 ///
-/// equality       → comparison ( ( "!=" | "==" ) comparison )* ;
-/// comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+/// equality       → bit_or ( ( "!=" | "==" | "is" ) bit_or )* ;
+/// bit_or         → bit_xor ( "|" bit_xor )* ;
+/// bit_xor        → bit_and ( "^" bit_and )* ;
+/// bit_and        → comparison ( "&" comparison )* ;
+/// comparison     → shift ( ( ">" | ">=" | "<" | "<=" ) shift )* ;
+/// shift          → term ( ( "<<" | ">>" ) term )* ;
 /// term           → factor ( ( "-" | "+" ) factor )* ;
-/// factor         → unary ( ( "/" | "*" ) unary )* ;
-/// unary          → ( "!" | "-" ) unary | call ;
-/// call           → primary ( "(" arguments? ")" )* ;
-/// arguments      → expression ( "," expression )* ;
+/// factor         → unary ( ( "/" | "*" | "%" ) unary )* ;
+/// unary          → ( "!" | "-" ) unary | power ;
+/// power          → call ( "**" unary )? ;
+/// call           → primary ( "(" arguments? ")" | "." IDENTIFIER | "[" expression "]" )* ;
+/// arguments      → element ( "," element )* ;
 /// primary        → "true" | "false" | "nil"
 ///                | NUMBER | STRING
 ///                | "(" expression ")"
+///                | "[" ( element ( "," element )* )? "]"
+///                | "{" ( entry ( "," entry )* )? "}"
+///                | switch
+///                | match
+///                | loop
+///                | "this" | "__line__" | "__file__"
 ///                | IDENTIFIER ;
+/// element        → "..."? expression ;
+/// entry          → expression ":" expression | "..." expression ;
+/// switch         → "switch" "(" expression ")" "{"
+///                  ( "case" expression ":" declaration* )*
+///                  "default" ":" declaration* "}" ;
+/// match          → "match" expression "{" ( pattern "=>" expression "," )*
+///                  "_" "=>" expression ","? "}" ;
+/// pattern        → "true" | "false" | "nil" | "-"? NUMBER | STRING ;
+/// loop           → "loop" block ;
 /// ```
 pub(crate) struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    errors: LoxErrors,
+    /// The number of loops enclosing the current position, to check that `break` is inside one.
+    loop_depth: usize,
+    /// Whether the current position is in a switch case that may fall through to the next one.
+    in_fallthrough_case: bool,
+    /// Whether the current position is in a class body, where `this` may be used.
+    in_class: bool,
+    /// Whether the current position is directly in an `init` method, which can't return a value.
+    in_initializer: bool,
+    /// Automatic semicolon insertion: whether a statement may also be terminated by a newline,
+    /// a closing '}' or the end of the file, rather than only by a ';'.
+    asi: bool,
+    /// The name of the file being parsed, which `__file__` is replaced with.
+    file: std::string::String,
 }
 
 impl Parser {
-    pub(crate) fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+    pub(crate) fn new(tokens: Vec<Token>, max_errors: usize) -> Self {
+        Self {
+            tokens,
+            current: 0,
+            errors: LoxErrors::new(max_errors),
+            loop_depth: 0,
+            in_fallthrough_case: false,
+            in_class: false,
+            in_initializer: false,
+            asi: false,
+            file: std::string::String::new(),
+        }
+    }
+
+    /// Create a parser with automatic semicolon insertion, where the trailing ';' of a statement
+    /// is optional when the statement is unambiguously terminated otherwise.
+    pub(crate) fn new_asi(tokens: Vec<Token>, max_errors: usize) -> Self {
+        Self {
+            asi: true,
+            ..Self::new(tokens, max_errors)
+        }
+    }
+
+    /// Set the file name that `__file__` is replaced with.
+    pub(crate) fn set_file(&mut self, file: &str) {
+        self.file = file.to_string();
     }
 
     /// expression     → equality ;
@@ -85,46 +180,90 @@ impl Parser {
     ///                | printStmt
     ///                | returnStmt
     ///                | whileStmt
+    ///                | withStmt
     ///                | block ;
     fn declaration(&mut self) -> Result<Stmt, LoxError> {
-        if self.match_token_type(Fun) {
-            return self.function("function");
-        }
-        let res = if self.match_token_type(Var) {
+        let start = self.current;
+        let res = if self.match_token_type(Class) {
+            self.class_declaration()
+        } else if self.match_token_type(Enum) {
+            self.enum_declaration()
+        } else if self.match_token_type(Fun) {
+            self.function("function")
+        } else if self.match_token_type(Var) {
             self.var_declaration()
         } else {
             self.statement()
         };
 
         if res.is_err() {
-            self.synchronize()
+            if self.asi {
+                self.synchronize_line(start)
+            } else {
+                self.synchronize()
+            }
         }
 
         res
     }
 
     /// statement      → exprStmt
+    ///                | assertStmt
+    ///                | breakStmt
+    ///                | continueStmt
+    ///                | fallthroughStmt
     ///                | forStmt
     ///                | ifStmt
+    ///                | unlessStmt
+    ///                | loopStmt
     ///                | printStmt
+    ///                | eprintStmt
+    ///                | switchStmt
     ///                | whileStmt
     ///                | block ;
     fn statement(&mut self) -> Result<Stmt, LoxError> {
+        if self.match_token_type(Assert) {
+            return self.assert_statement();
+        }
+        if self.match_token_type(Break) {
+            return self.break_statement();
+        }
+        if self.match_token_type(Continue) {
+            return self.continue_statement();
+        }
+        if self.match_token_type(Fallthrough) {
+            return self.fallthrough_statement();
+        }
         if self.match_token_type(For) {
             return self.for_statement();
         }
         if self.match_token_type(If) {
             return self.if_statement();
         }
+        if self.match_token_type(Unless) {
+            return self.unless_statement();
+        }
+        if self.match_token_type(Loop) {
+            return self.loop_statement();
+        }
         if self.match_token_type(Print) {
             return self.print_statement();
         }
+        if self.match_token_type(EPrint) {
+            return self.eprint_statement();
+        }
         if self.match_token_type(Return) {
             return self.return_statement();
         }
+        if self.match_token_type(Switch) {
+            return self.switch_statement();
+        }
         if self.match_token_type(While) {
             return self.while_statement();
         }
+        if self.match_token_type(With) {
+            return self.with_statement();
+        }
         if self.match_token_type(LeftBrace) {
             return Ok(Stmt::Block {
                 statements: self.block()?,
@@ -134,9 +273,57 @@ impl Parser {
         self.expression_statement()
     }
 
+    /// breakStmt      → "break" expression? ";" ;
+    fn break_statement(&mut self) -> Result<Stmt, LoxError> {
+        let keyword = self.previous().clone();
+        if self.loop_depth == 0 {
+            return Err(LoxError::from_token(
+                &keyword,
+                "Can't use 'break' outside of a loop.".to_string(),
+            ));
+        }
+        let value = if self.at_terminator() {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume_terminator("Expect ';' after 'break'.".to_string())?;
+
+        Ok(Stmt::Break { keyword, value })
+    }
+
+    /// continueStmt   → "continue" ";" ;
+    fn continue_statement(&mut self) -> Result<Stmt, LoxError> {
+        let keyword = self.previous().clone();
+        if self.loop_depth == 0 {
+            return Err(LoxError::from_token(
+                &keyword,
+                "Can't use 'continue' outside of a loop.".to_string(),
+            ));
+        }
+        self.consume_terminator("Expect ';' after 'continue'.".to_string())?;
+
+        Ok(Stmt::Continue { keyword })
+    }
+
+    /// fallthroughStmt → "fallthrough" ";" ;
+    fn fallthrough_statement(&mut self) -> Result<Stmt, LoxError> {
+        let keyword = self.previous().clone();
+        if !self.in_fallthrough_case {
+            return Err(LoxError::from_token(
+                &keyword,
+                "Can only use 'fallthrough' in a switch case that is followed by another case."
+                    .to_string(),
+            ));
+        }
+        self.consume_terminator("Expect ';' after 'fallthrough'.".to_string())?;
+
+        Ok(Stmt::Fallthrough { keyword })
+    }
+
     /// forStmt        → "for" "(" ( varDecl | exprStmt | ";" )
     ///                  expression? ";"
-    ///                  expression? ")" statement ;
+    ///                  expression? ")" loopBody ;
     fn for_statement(&mut self) -> Result<Stmt, LoxError> {
         self.consume(LeftParen, "Expect '(' after for.".to_string())?;
         let initializer = if self.match_token_type(Semicolon) {
@@ -159,28 +346,17 @@ impl Parser {
         };
         self.consume(RightParen, "Expect ')' after for clauses.".to_string())?;
 
-        let mut body = self.statement()?;
+        let body = self.loop_body()?;
+        let else_branch = self.loop_else(&body)?;
 
-        if let Some(increment) = increment {
-            body = Stmt::Block {
-                statements: vec![
-                    body.clone(),
-                    Stmt::Expression {
-                        expression: increment,
-                    },
-                ],
-            }
-        }
-        let condition = if condition.is_none() {
-            Expr::Literal {
-                value: Literal::Bool(true),
-            }
-        } else {
-            condition.unwrap()
-        };
+        let condition = condition.unwrap_or(Expr::Literal {
+            value: Literal::Bool(true),
+        });
         let mut body = Stmt::While {
             condition,
             body: Box::new(body),
+            increment,
+            else_branch,
         };
         if let Some(initializer) = initializer {
             body = Stmt::Block {
@@ -191,14 +367,97 @@ impl Parser {
         Ok(body)
     }
 
-    /// whileStmt      → "while" "(" expression ")" statement ;
+    /// whileStmt      → "while" "(" ( "var" IDENTIFIER "=" )? expression ")" loopBody ;
+    ///
+    /// With a declaration, the expression is assigned to the variable before every iteration,
+    /// and the loop runs while that value is truthy. The variable is scoped to the loop.
     fn while_statement(&mut self) -> Result<Stmt, LoxError> {
         self.consume(LeftParen, "Expect '(' after while.".to_string())?;
-        let condition = self.expression()?;
+        let declaration = if self.match_token_type(Var) {
+            let name = self
+                .consume(Identifier, "Expect variable name.".to_string())?
+                .clone();
+            self.consume(
+                Equal,
+                "Expect '=' after variable name in while condition.".to_string(),
+            )?;
+            Some(name)
+        } else {
+            None
+        };
+        let mut condition = self.expression()?;
         self.consume(RightParen, "Expect ')' after while condition.".to_string())?;
-        let body = Box::new(self.statement()?);
+        let body = Box::new(self.loop_body()?);
+        let else_branch = self.loop_else(&body)?;
+
+        if let Some(name) = &declaration {
+            condition = Expr::Assign {
+                name: name.clone(),
+                value: Box::new(condition),
+                depth: Depth::default(),
+            };
+        }
+        let body = Stmt::While {
+            condition,
+            body,
+            increment: None,
+            else_branch,
+        };
+
+        Ok(match declaration {
+            Some(name) => Stmt::Block {
+                statements: vec![
+                    Stmt::Var {
+                        name,
+                        initializer: None,
+                    },
+                    body,
+                ],
+            },
+            None => body,
+        })
+    }
+
+    /// switchStmt     → switch ";"? ;
+    ///
+    /// Like a block, a switch that starts a statement needs no ';' after it. It is still an
+    /// expression, so it can be the trailing expression of a block.
+    fn switch_statement(&mut self) -> Result<Stmt, LoxError> {
+        let expression = self.switch()?;
+        self.match_token_type(Semicolon);
+
+        Ok(Stmt::Expression { expression })
+    }
+
+    /// loopStmt       → loop ";"? ;
+    ///
+    /// Like a switch, a loop that starts a statement needs no ';' after it, and is still an
+    /// expression.
+    fn loop_statement(&mut self) -> Result<Stmt, LoxError> {
+        let expression = self.loop_expression()?;
+        self.match_token_type(Semicolon);
+
+        Ok(Stmt::Expression { expression })
+    }
+
+    /// Parse the body of a loop, in which `break` may be used.
+    fn loop_body(&mut self) -> Result<Stmt, LoxError> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
 
-        Ok(Stmt::While { condition, body })
+    /// Parse the optional else branch of a loop, which runs when it finishes without a `break`.
+    ///
+    /// Only a loop with a block as its `body` can have one. Otherwise, in
+    /// `if (a) while (b) c; else d;` the else would be taken from the if-statement.
+    fn loop_else(&mut self, body: &Stmt) -> Result<Option<WrappedStmt>, LoxError> {
+        if matches!(body, Stmt::Block { .. }) && self.match_token_type(Else) {
+            return Ok(Some(Box::new(self.statement()?)));
+        }
+
+        Ok(None)
     }
 
     /// ifStmt         → "if" "(" expression ")" statement
@@ -222,14 +481,158 @@ impl Parser {
         })
     }
 
+    /// unlessStmt     → "unless" "(" expression ")" statement ;
+    ///
+    /// This is sugar for an if-statement with a negated condition, and without an else branch.
+    fn unless_statement(&mut self) -> Result<Stmt, LoxError> {
+        let keyword = self.previous().clone();
+        self.consume(LeftParen, "Expect '(' after unless.".to_string())?;
+        let condition = self.expression()?;
+        self.consume(RightParen, "Expect ')' after unless condition.".to_string())?;
+
+        let then_branch = Box::new(self.statement()?);
+        if self.check(Else) {
+            return Err(LoxError::from_token(
+                self.peek(),
+                "Can't have an else branch after unless.".to_string(),
+            ));
+        }
+
+        let bang = Token::new(Bang, "!".to_string(), None, keyword.line(), keyword.col());
+        Ok(Stmt::If {
+            condition: Expr::Unary {
+                operator: bang,
+                right: Box::new(condition),
+            },
+            then_branch,
+            else_branch: None,
+        })
+    }
+
+    /// withStmt       → "with" "(" "var" IDENTIFIER "=" expression ")" block ;
+    ///
+    /// The variable holds a resource, whose `close` method is called when the block is left.
+    fn with_statement(&mut self) -> Result<Stmt, LoxError> {
+        let keyword = self.previous().clone();
+        self.consume(LeftParen, "Expect '(' after with.".to_string())?;
+        self.consume(Var, "Expect 'var' after '('.".to_string())?;
+        let name = self
+            .consume(Identifier, "Expect variable name.".to_string())?
+            .clone();
+        self.consume(Equal, "Expect '=' after variable name.".to_string())?;
+        let initializer = self.expression()?;
+        self.consume(RightParen, "Expect ')' after with resource.".to_string())?;
+        self.consume(LeftBrace, "Expect '{' before with body.".to_string())?;
+        let body = self.block()?;
+
+        Ok(Stmt::With {
+            keyword,
+            name,
+            initializer,
+            body,
+        })
+    }
+
     /// exprStmt       → expression ";" ;
     fn expression_statement(&mut self) -> Result<Stmt, LoxError> {
         let value = self.expression()?;
-        self.consume(Semicolon, "Expect ';' after expression.".to_string())?;
+        // The trailing expression of a block, which it evaluates to, needs no ';'.
+        if !self.check(RightBrace) {
+            self.consume_terminator("Expect ';' after expression.".to_string())?;
+        }
 
         Ok(Stmt::Expression { expression: value })
     }
 
+    /// classDecl      → "class" IDENTIFIER ( "<" IDENTIFIER )?
+    ///                  "{" ( function | staticField )* "}" ;
+    fn class_declaration(&mut self) -> Result<Stmt, LoxError> {
+        let name = self
+            .consume(Identifier, "Expect class name.".to_string())?
+            .clone();
+        let superclass = if self.match_token_type(Less) {
+            let superclass = self
+                .consume(Identifier, "Expect superclass name.".to_string())?
+                .clone();
+            if superclass.lexeme() == name.lexeme() {
+                return Err(LoxError::from_token(
+                    &superclass,
+                    "A class can't inherit from itself.".to_string(),
+                ));
+            }
+            Some(Expr::Variable {
+                name: superclass,
+                depth: Depth::default(),
+            })
+        } else {
+            None
+        };
+        self.consume(LeftBrace, "Expect '{' before class body.".to_string())?;
+        let in_class = std::mem::replace(&mut self.in_class, true);
+        let body = self.class_body();
+        self.in_class = in_class;
+        let (methods, statics) = body?;
+        self.consume(RightBrace, "Expect '}' after class body.".to_string())?;
+
+        Ok(Stmt::Class {
+            name,
+            superclass,
+            methods,
+            statics,
+        })
+    }
+
+    /// enumDecl       → "enum" IDENTIFIER "{" ( IDENTIFIER ( "," IDENTIFIER )* ","? )? "}" ;
+    fn enum_declaration(&mut self) -> Result<Stmt, LoxError> {
+        let name = self
+            .consume(Identifier, "Expect enum name.".to_string())?
+            .clone();
+        self.consume(LeftBrace, "Expect '{' before enum body.".to_string())?;
+        let mut variants = Vec::new();
+        while !self.check(RightBrace) && !self.is_at_end() {
+            variants.push(
+                self.consume(Identifier, "Expect variant name.".to_string())?
+                    .clone(),
+            );
+            if !self.match_token_type(Comma) {
+                break;
+            }
+        }
+        self.consume(RightBrace, "Expect '}' after enum body.".to_string())?;
+
+        Ok(Stmt::Enum { name, variants })
+    }
+
+    /// Parse the methods and the static fields in a class body, up to its closing '}'.
+    fn class_body(&mut self) -> Result<(Vec<Stmt>, Vec<Stmt>), LoxError> {
+        let mut methods = Vec::new();
+        let mut statics = Vec::new();
+        while !self.check(RightBrace) && !self.is_at_end() {
+            if self.match_token_type(Static) {
+                statics.push(self.static_field()?);
+            } else {
+                methods.push(self.function("method")?);
+            }
+        }
+
+        Ok((methods, statics))
+    }
+
+    /// staticField    → "static" IDENTIFIER "=" expression ";" ;
+    fn static_field(&mut self) -> Result<Stmt, LoxError> {
+        let name = self
+            .consume(Identifier, "Expect static field name.".to_string())?
+            .clone();
+        self.consume(Equal, "Expect '=' after static field name.".to_string())?;
+        let initializer = self.expression()?;
+        self.consume_terminator("Expect ';' after static field.".to_string())?;
+
+        Ok(Stmt::Var {
+            name,
+            initializer: Some(initializer),
+        })
+    }
+
     fn function(&mut self, kind: &str) -> Result<Stmt, LoxError> {
         let name = self
             .consume(Identifier, format!("Expect {kind} name."))?
@@ -258,12 +661,24 @@ impl Parser {
         self.consume(RightParen, "Expect ')' after parameters.".to_string())?;
 
         self.consume(LeftBrace, format!("Expect '{{' before {kind} body."))?;
-        let body = self.block()?;
+        // A break or fallthrough can't jump out of a function into a loop or switch surrounding
+        // its declaration.
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        let in_fallthrough_case = std::mem::take(&mut self.in_fallthrough_case);
+        let in_initializer = std::mem::replace(
+            &mut self.in_initializer,
+            kind == "method" && name.lexeme() == "init",
+        );
+        let body = self.block();
+        self.loop_depth = loop_depth;
+        self.in_fallthrough_case = in_fallthrough_case;
+        self.in_initializer = in_initializer;
+        let body = body?.into();
 
         Ok(Stmt::Function { name, params, body })
     }
 
-    /// block          → "{" declaration* "}" ;
+    /// block          → "{" declaration* expression? "}" ;
     fn block(&mut self) -> Result<Vec<Stmt>, LoxError> {
         let mut statements = Vec::new();
 
@@ -275,20 +690,44 @@ impl Parser {
         Ok(statements)
     }
 
-    /// assignment     → IDENTIFIER "=" assignment
-    ///                | logic_or ;
+    /// assignment     → ( call "." )? IDENTIFIER "=" assignment
+    ///                | call "[" expression "]" "=" assignment
+    ///                | conditional ;
     fn assignment(&mut self) -> Result<Expr, LoxError> {
-        let expr = self.logic_or()?;
+        let expr = self.conditional()?;
 
         if self.match_token_type(Equal) {
             let equals = self.previous().clone();
             let value = self.assignment()?;
 
-            if let Expr::Variable { name } = expr {
-                return Ok(Expr::Assign {
-                    name,
-                    value: Box::new(value),
-                });
+            match expr {
+                Expr::Variable { name, depth } => {
+                    return Ok(Expr::Assign {
+                        name,
+                        value: Box::new(value),
+                        depth,
+                    })
+                }
+                Expr::Get { object, name } => {
+                    return Ok(Expr::Set {
+                        object,
+                        name,
+                        value: Box::new(value),
+                    })
+                }
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                } => {
+                    return Ok(Expr::SetIndex {
+                        object,
+                        bracket,
+                        index,
+                        value: Box::new(value),
+                    })
+                }
+                _ => {}
             }
 
             return Err(LoxError::from_token(
@@ -300,19 +739,36 @@ impl Parser {
         Ok(expr)
     }
 
+    /// conditional    → logic_or ( "?" expression ":" conditional )? ;
+    fn conditional(&mut self) -> Result<Expr, LoxError> {
+        let condition = self.logic_or()?;
+
+        if self.match_token_type(Question) {
+            let then_branch = self.expression()?;
+            self.consume(Colon, "Expect ':' after then branch of '?'.".to_string())?;
+            let else_branch = self.conditional()?;
+            return Ok(Expr::Ternary {
+                condition: Box::new(condition),
+                then_branch: Box::new(then_branch),
+                else_branch: Box::new(else_branch),
+            });
+        }
+
+        Ok(condition)
+    }
+
     /// logic_or       → logic_and ( "or" logic_and )* ;
     fn logic_or(&mut self) -> Result<Expr, LoxError> {
-        let expr = self.logic_and()?;
+        let mut expr = self.logic_and()?;
 
-        if self.match_token_type(Or) {
+        while self.match_token_type(Or) {
             let operator = self.previous().clone();
             let right = self.logic_and()?;
-            let expr = Expr::Logical {
+            expr = Expr::Logical {
                 left: Box::new(expr),
                 operator,
                 right: Box::new(right),
-            };
-            return Ok(expr);
+            }
         }
 
         Ok(expr)
@@ -320,17 +776,16 @@ impl Parser {
 
     /// logic_and      → equality ( "and" equality )* ;
     fn logic_and(&mut self) -> Result<Expr, LoxError> {
-        let expr = self.equality()?;
+        let mut expr = self.equality()?;
 
-        if self.match_token_type(And) {
+        while self.match_token_type(And) {
             let operator = self.previous().clone();
             let right = self.equality()?;
-            let expr = Expr::Logical {
+            expr = Expr::Logical {
                 left: Box::new(expr),
                 operator,
                 right: Box::new(right),
-            };
-            return Ok(expr);
+            }
         }
 
         Ok(expr)
@@ -339,22 +794,59 @@ impl Parser {
     /// printStmt      → "print" expression ";" ;
     fn print_statement(&mut self) -> Result<Stmt, LoxError> {
         let value = self.expression()?;
-        self.consume(Semicolon, "Expect ';' after value.".to_string())?;
+        self.consume_terminator("Expect ';' after value.".to_string())?;
 
         Ok(Stmt::Print { expression: value })
     }
 
+    /// assertStmt     → "assert" expression ( "," expression )? ";" ;
+    fn assert_statement(&mut self) -> Result<Stmt, LoxError> {
+        let keyword = self.previous().clone();
+        let start = self.current;
+        let condition = self.expression()?;
+        let source = self.source_since(start);
+
+        let message = if self.match_token_type(Comma) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume_terminator("Expect ';' after assertion.".to_string())?;
+
+        Ok(Stmt::Assert {
+            keyword,
+            condition,
+            message,
+            source,
+        })
+    }
+
+    /// eprintStmt     → "eprint" expression ";" ;
+    fn eprint_statement(&mut self) -> Result<Stmt, LoxError> {
+        let value = self.expression()?;
+        self.consume_terminator("Expect ';' after value.".to_string())?;
+
+        Ok(Stmt::EPrint { expression: value })
+    }
+
     /// returnStmt     → "return" expression? ";" ;
     fn return_statement(&mut self) -> ReturnOrError {
         let keyword = self.previous().clone();
         // Distinguish between `return;` and `return val;`
-        let value = if self.check(Semicolon) {
+        let value = if self.at_terminator() {
             None
         } else {
             Some(self.expression()?)
         };
+        if value.is_some() && self.in_initializer {
+            return Err(LoxError::from_token(
+                &keyword,
+                "Can't return a value from an initializer.".to_string(),
+            ));
+        }
 
-        self.consume(Semicolon, "Expect ';' after return value.".to_string())?;
+        self.consume_terminator("Expect ';' after return value.".to_string())?;
 
         Ok(Stmt::Return { keyword, value })
     }
@@ -371,19 +863,67 @@ impl Parser {
             None
         };
 
-        self.consume(
-            Semicolon,
-            "Expect ';' after variable declaration.".to_string(),
-        )?;
+        self.consume_terminator("Expect ';' after variable declaration.".to_string())?;
 
         Ok(Stmt::Var { name, initializer })
     }
 
-    /// equality       → comparison ( ( "!=" | "==" ) comparison )* ;
+    /// equality       → bit_or ( ( "!=" | "==" | "is" ) bit_or )* ;
     fn equality(&mut self) -> Result<Expr, LoxError> {
+        let mut expr = self.bit_or()?;
+
+        while self.match_(&[BangEqual, EqualEqual, Is]) {
+            let operator = self.previous().clone();
+            let right = self.bit_or()?;
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            }
+        }
+
+        Ok(expr)
+    }
+
+    /// bit_or         → bit_xor ( "|" bit_xor )* ;
+    fn bit_or(&mut self) -> Result<Expr, LoxError> {
+        let mut expr = self.bit_xor()?;
+
+        while self.match_token_type(Pipe) {
+            let operator = self.previous().clone();
+            let right = self.bit_xor()?;
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            }
+        }
+
+        Ok(expr)
+    }
+
+    /// bit_xor        → bit_and ( "^" bit_and )* ;
+    fn bit_xor(&mut self) -> Result<Expr, LoxError> {
+        let mut expr = self.bit_and()?;
+
+        while self.match_token_type(Caret) {
+            let operator = self.previous().clone();
+            let right = self.bit_and()?;
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            }
+        }
+
+        Ok(expr)
+    }
+
+    /// bit_and        → comparison ( "&" comparison )* ;
+    fn bit_and(&mut self) -> Result<Expr, LoxError> {
         let mut expr = self.comparison()?;
 
-        while self.match_(&[BangEqual, EqualEqual]) {
+        while self.match_token_type(Ampersand) {
             let operator = self.previous().clone();
             let right = self.comparison()?;
             expr = Expr::Binary {
@@ -396,11 +936,28 @@ impl Parser {
         Ok(expr)
     }
 
-    /// comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+    /// comparison     → shift ( ( ">" | ">=" | "<" | "<=" ) shift )* ;
     fn comparison(&mut self) -> Result<Expr, LoxError> {
-        let mut expr = self.term()?;
+        let mut expr = self.shift()?;
 
         while self.match_(&[Greater, GreaterEqual, Less, LessEqual]) {
+            let operator = self.previous().clone();
+            let right = self.shift()?;
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            }
+        }
+
+        Ok(expr)
+    }
+
+    /// shift          → term ( ( "<<" | ">>" ) term )* ;
+    fn shift(&mut self) -> Result<Expr, LoxError> {
+        let mut expr = self.term()?;
+
+        while self.match_(&[LessLess, GreaterGreater]) {
             let operator = self.previous().clone();
             let right = self.term()?;
             expr = Expr::Binary {
@@ -430,11 +987,11 @@ impl Parser {
         Ok(expr)
     }
 
-    /// factor         → unary ( ( "/" | "*" ) unary )* ;
+    /// factor         → unary ( ( "/" | "*" | "%" ) unary )* ;
     fn factor(&mut self) -> Result<Expr, LoxError> {
         let mut expr = self.unary()?;
 
-        while self.match_(&[Slash, Star]) {
+        while self.match_(&[Slash, Star, Percent]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
             expr = Expr::Binary {
@@ -447,7 +1004,7 @@ impl Parser {
         Ok(expr)
     }
 
-    /// unary          → ( "!" | "-" ) unary | call ;
+    /// unary          → ( "!" | "-" ) unary | power ;
     fn unary(&mut self) -> Result<Expr, LoxError> {
         if self.match_(&[Bang, Minus]) {
             let operator = self.previous().clone();
@@ -458,16 +1015,53 @@ impl Parser {
             });
         }
 
-        self.call()
+        self.power()
+    }
+
+    /// power          → call ( "**" unary )? ;
+    ///
+    /// The right operand may itself be a power, which makes `**` right-associative.
+    fn power(&mut self) -> Result<Expr, LoxError> {
+        let expr = self.call()?;
+
+        if self.match_token_type(StarStar) {
+            let operator = self.previous().clone();
+            let right = self.unary()?;
+            return Ok(Expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            });
+        }
+
+        Ok(expr)
     }
 
-    /// call           → primary ( "(" arguments? ")" )* ;
+    /// call           → primary ( "(" arguments? ")" | "." IDENTIFIER | "[" expression "]" )* ;
     fn call(&mut self) -> Result<Expr, LoxError> {
         let mut expr = self.primary()?;
 
         loop {
             if self.match_token_type(LeftParen) {
                 expr = self.finish_call(expr)?;
+            } else if self.match_token_type(Dot) {
+                let name = self
+                    .consume(Identifier, "Expect property name after '.'.".to_string())?
+                    .clone();
+                expr = Expr::Get {
+                    object: Box::new(expr),
+                    name,
+                };
+            } else if self.match_token_type(LeftBracket) {
+                let index = self.expression()?;
+                let bracket = self
+                    .consume(RightBracket, "Expect ']' after index.".to_string())?
+                    .clone();
+                expr = Expr::Index {
+                    object: Box::new(expr),
+                    bracket,
+                    index: Box::new(index),
+                };
             } else {
                 break;
             }
@@ -487,7 +1081,7 @@ impl Parser {
         })
     }
 
-    /// arguments      → expression ( "," expression )* ;
+    /// arguments      → element ( "," element )* ;
     fn arguments(&mut self) -> Result<Vec<Expr>, LoxError> {
         let mut arguments = Vec::new();
         if !self.check(RightParen) {
@@ -498,7 +1092,7 @@ impl Parser {
                         "Can't have more than 255 arguments.".to_string(),
                     ));
                 }
-                arguments.push(self.expression()?);
+                arguments.push(self.element()?);
                 if !self.match_token_type(Comma) {
                     break;
                 }
@@ -508,9 +1102,29 @@ impl Parser {
         Ok(arguments)
     }
 
+    /// element        → "..."? expression ;
+    ///
+    /// An element of a list literal or an argument of a call, which may be spread.
+    fn element(&mut self) -> Result<Expr, LoxError> {
+        if self.match_token_type(DotDotDot) {
+            let ellipsis = self.previous().clone();
+            return Ok(Expr::Spread {
+                ellipsis,
+                expression: Box::new(self.expression()?),
+            });
+        }
+
+        self.expression()
+    }
+
     /// primary        → "true" | "false" | "nil"
     ///                | NUMBER | STRING
     ///                | "(" expression ")"
+    ///                | "[" ( element ( "," element )* )? "]"
+    ///                | "{" ( entry ( "," entry )* )? "}"
+    ///                | switch
+    ///                | match
+    ///                | "this" | "__line__" | "__file__"
     ///                | IDENTIFIER ;
     fn primary(&mut self) -> Result<Expr, LoxError> {
         if self.match_token_type(False) {
@@ -539,9 +1153,38 @@ impl Parser {
             });
         }
 
+        if self.match_token_type(This) {
+            let keyword = self.previous().clone();
+            if !self.in_class {
+                return Err(LoxError::from_token(
+                    &keyword,
+                    "Can't use 'this' outside of a class.".to_string(),
+                ));
+            }
+            return Ok(Expr::This {
+                keyword,
+                depth: Depth::default(),
+            });
+        }
+
         if self.match_token_type(Identifier) {
+            // The magic identifiers stand for where they are written in the source.
+            match self.previous().lexeme() {
+                "__line__" => {
+                    return Ok(Expr::Literal {
+                        value: Literal::Number(self.previous().line() as Number),
+                    })
+                }
+                "__file__" => {
+                    return Ok(Expr::Literal {
+                        value: Literal::String(self.file.clone()),
+                    })
+                }
+                _ => {}
+            }
             return Ok(Expr::Variable {
                 name: self.previous().clone(),
+                depth: Depth::default(),
             });
         }
 
@@ -553,6 +1196,54 @@ impl Parser {
             });
         }
 
+        if self.match_token_type(LeftBracket) {
+            let mut elements = Vec::new();
+            if !self.check(RightBracket) {
+                loop {
+                    elements.push(self.element()?);
+                    if !self.match_token_type(Comma) {
+                        break;
+                    }
+                }
+            }
+            self.consume(RightBracket, "Expect ']' after list elements.".to_string())?;
+            return Ok(Expr::List { elements });
+        }
+
+        if self.match_token_type(LeftBrace) {
+            let mut entries = Vec::new();
+            if !self.check(RightBrace) {
+                loop {
+                    if self.check(DotDotDot) {
+                        entries.push(MapEntry::Spread(self.element()?));
+                    } else {
+                        let key = self.expression()?;
+                        self.consume(Colon, "Expect ':' after map key.".to_string())?;
+                        entries.push(MapEntry::Pair(key, self.expression()?));
+                    }
+                    if !self.match_token_type(Comma) {
+                        break;
+                    }
+                }
+            }
+            let brace = self
+                .consume(RightBrace, "Expect '}' after map entries.".to_string())?
+                .clone();
+            return Ok(Expr::Map { brace, entries });
+        }
+
+        if self.match_token_type(Switch) {
+            return self.switch();
+        }
+
+        if self.match_token_type(Match) {
+            return self.match_expression();
+        }
+
+        if self.match_token_type(Loop) {
+            return self.loop_expression();
+        }
+
         let unexpected = self.peek();
         Err(LoxError::from_token(
             unexpected,
@@ -560,6 +1251,23 @@ impl Parser {
         ))
     }
 
+    /// Reconstruct the source text spanned by the tokens from `start` up to the current token.
+    ///
+    /// Whitespace between tokens is collapsed into a single space.
+    fn source_since(&self, start: usize) -> std::string::String {
+        let mut source = std::string::String::new();
+        let mut previous: Option<&Token> = None;
+        for token in &self.tokens[start..self.current] {
+            if previous.is_some_and(|previous| !token.follows(previous)) {
+                source.push(' ');
+            }
+            source.push_str(token.lexeme());
+            previous = Some(token);
+        }
+
+        source
+    }
+
     fn peek(&self) -> &Token {
         &self.tokens[self.current]
     }
@@ -619,6 +1327,185 @@ impl Parser {
         Err(LoxError::from_token(unexpected, message))
     }
 
+    /// Whether the current statement ends here. That is the case at a ';', or when automatic
+    /// semicolon insertion is enabled, at a newline, a '}' or the end of the file.
+    fn at_terminator(&self) -> bool {
+        if self.check(Semicolon) {
+            return true;
+        }
+
+        self.asi
+            && (self.peek().line() > self.previous().line()
+                || self.check(RightBrace)
+                || self.is_at_end())
+    }
+
+    /// Consume the ';' that terminates a statement. With automatic semicolon insertion, a missing
+    /// ';' is fine as long as the statement is terminated otherwise.
+    fn consume_terminator(&mut self, message: std::string::String) -> Result<(), LoxError> {
+        if !self.at_terminator() {
+            return Err(LoxError::from_token(self.peek(), message));
+        }
+
+        self.match_token_type(Semicolon);
+        Ok(())
+    }
+
+    /// loop           → "loop" block ;
+    ///
+    /// Runs the block until it is broken out of, and evaluates to the value of that `break`.
+    fn loop_expression(&mut self) -> Result<Expr, LoxError> {
+        let keyword = self.previous().clone();
+        self.consume(LeftBrace, "Expect '{' after loop.".to_string())?;
+        self.loop_depth += 1;
+        let body = self.block();
+        self.loop_depth -= 1;
+
+        Ok(Expr::Loop {
+            keyword,
+            body: body?,
+        })
+    }
+
+    /// switch         → "switch" "(" expression ")" "{"
+    ///                  ( "case" expression ":" declaration* )*
+    ///                  "default" ":" declaration* "}" ;
+    ///
+    /// The default case is required, such that a switch always has a value.
+    fn switch(&mut self) -> Result<Expr, LoxError> {
+        let keyword = self.previous().clone();
+        self.consume(LeftParen, "Expect '(' after 'switch'.".to_string())?;
+        let subject = self.expression()?;
+        self.consume(RightParen, "Expect ')' after switch value.".to_string())?;
+        self.consume(LeftBrace, "Expect '{' before switch cases.".to_string())?;
+
+        let mut cases = Vec::new();
+        while self.match_token_type(Case) {
+            let value = self.expression()?;
+            self.consume(Colon, "Expect ':' after case value.".to_string())?;
+            cases.push(SwitchCase {
+                value,
+                body: self.case_body(true)?,
+            });
+        }
+
+        if !self.match_token_type(Default) {
+            return Err(LoxError::from_token(
+                self.peek(),
+                "Expect a 'default' case at the end of the switch.".to_string(),
+            ));
+        }
+        self.consume(Colon, "Expect ':' after 'default'.".to_string())?;
+        let default = self.case_body(false)?;
+        if self.check(Case) {
+            return Err(LoxError::from_token(
+                self.peek(),
+                "The 'default' case must be the last case of the switch.".to_string(),
+            ));
+        }
+        self.consume(RightBrace, "Expect '}' after switch cases.".to_string())?;
+
+        Ok(Expr::Switch {
+            keyword,
+            subject: Box::new(subject),
+            cases,
+            default,
+        })
+    }
+
+    /// match          → "match" expression "{" ( pattern "=>" expression "," )*
+    ///                  "_" "=>" expression ","? "}" ;
+    ///
+    /// The wildcard arm is required, such that a match always has a value.
+    fn match_expression(&mut self) -> Result<Expr, LoxError> {
+        let keyword = self.previous().clone();
+        let subject = self.expression()?;
+        self.consume(LeftBrace, "Expect '{' before match arms.".to_string())?;
+
+        let mut arms = Vec::new();
+        let wildcard = loop {
+            if self.check(Identifier) && self.peek().lexeme() == "_" {
+                self.advance();
+                self.consume(FatArrow, "Expect '=>' after pattern.".to_string())?;
+                break self.expression()?;
+            }
+            if self.check(RightBrace) || self.is_at_end() {
+                return Err(LoxError::from_token(
+                    self.peek(),
+                    "Expect a '_' arm at the end of the match.".to_string(),
+                ));
+            }
+
+            let pattern = self.pattern()?;
+            self.consume(FatArrow, "Expect '=>' after pattern.".to_string())?;
+            let body = self.expression()?;
+            arms.push(MatchArm { pattern, body });
+            // Leave a missing wildcard arm to be reported above.
+            if !self.match_token_type(Comma) && !self.check(RightBrace) {
+                return Err(LoxError::from_token(
+                    self.peek(),
+                    "Expect ',' after match arm.".to_string(),
+                ));
+            }
+        };
+
+        self.match_token_type(Comma);
+        if !self.check(RightBrace) {
+            return Err(LoxError::from_token(
+                self.peek(),
+                "The '_' arm must be the last arm of the match.".to_string(),
+            ));
+        }
+        self.advance();
+
+        Ok(Expr::Match {
+            keyword,
+            subject: Box::new(subject),
+            arms,
+            wildcard: Box::new(wildcard),
+        })
+    }
+
+    /// pattern        → "true" | "false" | "nil" | "-"? NUMBER | STRING ;
+    fn pattern(&mut self) -> Result<Literal, LoxError> {
+        let token = self.advance().clone();
+        match token.token_type() {
+            True => Ok(Literal::Bool(true)),
+            False => Ok(Literal::Bool(false)),
+            Nil => Ok(Literal::Nil),
+            // Both are guaranteed to carry a literal by the scanner.
+            Number | String => Ok(token.literal().unwrap()),
+            Minus if self.match_token_type(Number) => Ok(Literal::Number(
+                -self.previous().literal().unwrap().number().unwrap(),
+            )),
+            _ => Err(LoxError::from_token(
+                &token,
+                "Expect a literal or '_' as pattern.".to_string(),
+            )),
+        }
+    }
+
+    /// Parse the statements of a switch case, up to the next case or the end of the switch. The
+    /// case may only continue into the next one using `fallthrough` if `may_fall_through`.
+    fn case_body(&mut self, may_fall_through: bool) -> Result<Vec<Stmt>, LoxError> {
+        let in_fallthrough_case =
+            std::mem::replace(&mut self.in_fallthrough_case, may_fall_through);
+        let mut statements = Vec::new();
+        let body = loop {
+            if self.check(Case) || self.check(Default) || self.check(RightBrace) || self.is_at_end()
+            {
+                break Ok(statements);
+            }
+            match self.declaration() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => break Err(error),
+            }
+        };
+        self.in_fallthrough_case = in_fallthrough_case;
+
+        body
+    }
+
     fn synchronize(&mut self) {
         self.advance();
 
@@ -628,7 +1515,8 @@ impl Parser {
             }
 
             match self.peek().token_type() {
-                Class | Fun | Var | For | If | While | Print | Return => return,
+                Assert | Class | Enum | Fun | Var | For | If | Unless | While | With | Loop
+                | Print | EPrint | Return | Switch | Match => return,
                 _ => {}
             }
 
@@ -636,10 +1524,37 @@ impl Parser {
         }
     }
 
-    pub(crate) fn parse(mut self) -> Result<Vec<Stmt>, LoxError> {
+    /// Recover from an error by skipping to the next line, for when newlines end statements. Unlike
+    /// [`Parser::synchronize`], this won't skip over a following line that does not start with a
+    /// keyword. The `start` is where the broken statement began.
+    fn synchronize_line(&mut self, start: usize) {
+        // The error is at the start of a line after the broken statement, so that line may be
+        // fine on its own.
+        if self.current > start && self.peek().line() > self.previous().line() {
+            return;
+        }
+
+        self.advance();
+        while !self.is_at_end() && self.peek().line() == self.previous().line() {
+            self.advance();
+        }
+    }
+
+    /// Parse the tokens into statements.
+    ///
+    /// After an error, the parser synchronizes and carries on, such that all errors can be
+    /// reported at once.
+    pub(crate) fn parse(mut self) -> Result<Vec<Stmt>, LoxErrors> {
         let mut statements = Vec::new();
         while !self.is_at_end() {
-            statements.push(self.declaration()?)
+            match self.declaration() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => self.errors.push(error),
+            }
+        }
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
         }
 
         Ok(statements)
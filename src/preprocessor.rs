@@ -0,0 +1,69 @@
+use std::collections::HashSet;
+
+use crate::{LoxError, LoxErrors};
+
+/// Apply the `#if FLAG` ... `#endif` directives in `source`, which keep the lines in between only
+/// if `FLAG` is among the `flags`. Directives may be nested.
+///
+/// The directives and any excluded lines are blanked out rather than removed, so the line numbers
+/// in later errors still point into the original source.
+pub(crate) fn preprocess(
+    source: &str,
+    flags: &HashSet<String>,
+    max_errors: usize,
+) -> Result<String, LoxErrors> {
+    let mut errors = LoxErrors::new(max_errors);
+    let mut output = String::with_capacity(source.len());
+    // The enclosing `#if` directives, as their line, flag, and whether their lines are kept.
+    let mut conditions: Vec<(usize, &str, bool)> = Vec::new();
+
+    for (n, line) in source.split_inclusive('\n').enumerate() {
+        let line_number = n + 1;
+        let directive = line.trim();
+        let keep = conditions.last().is_none_or(|&(_, _, keep)| keep);
+
+        let flag = directive
+            .strip_prefix("#if")
+            .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace));
+        if let Some(flag) = flag {
+            let flag = flag.trim();
+            if flag.is_empty() || flag.contains(char::is_whitespace) {
+                errors.push(LoxError::new(
+                    line_number,
+                    directive.len(),
+                    "Expect a single flag name after '#if'.".to_string(),
+                ));
+            }
+            conditions.push((line_number, flag, keep && flags.contains(flag)));
+        } else if directive == "#endif" {
+            if conditions.pop().is_none() {
+                errors.push(LoxError::new(
+                    line_number,
+                    directive.len(),
+                    "Found '#endif' without a matching '#if'.".to_string(),
+                ));
+            }
+        } else if keep {
+            output.push_str(line);
+            continue;
+        }
+
+        if line.ends_with('\n') {
+            output.push('\n');
+        }
+    }
+
+    for (line, flag, _) in conditions {
+        errors.push(LoxError::new(
+            line,
+            "#if ".len() + flag.len(),
+            format!("Unterminated '#if {flag}', expect a matching '#endif'."),
+        ));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(output)
+}
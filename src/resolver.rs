@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+
+use crate::ast::{Depth, Expr, MapEntry, Stmt};
+use crate::token::Token;
+use crate::{LoxError, LoxErrors};
+
+/// Finds out, before a program runs, which declaration every use of a variable refers to. The
+/// depth it stores in each use counts the scopes between the two, which mirror the environments
+/// the interpreter creates: one per block, and for a call one for the parameters and one for the
+/// body.
+pub(crate) struct Resolver {
+    /// The local scopes around the current position, innermost last. Each maps the names declared
+    /// in it to whether their declaration is done, such that an initializer can't read its own
+    /// variable. Globals are not tracked, and any variable not found here is taken to be one.
+    scopes: Vec<HashMap<String, bool>>,
+    /// Whether the current position is in a function, where `return` may be used.
+    in_function: bool,
+    errors: LoxErrors,
+}
+
+impl Resolver {
+    pub(crate) fn new(max_errors: usize) -> Self {
+        Self {
+            scopes: Vec::new(),
+            in_function: false,
+            errors: LoxErrors::new(max_errors),
+        }
+    }
+
+    /// Resolve all variables in the `statements`.
+    ///
+    /// The resolver carries on after an error, such that all errors can be reported at once.
+    pub(crate) fn resolve(mut self, statements: &[Stmt]) -> Result<(), LoxErrors> {
+        self.statements(statements);
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        Ok(())
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Assert {
+                condition, message, ..
+            } => {
+                self.expression(condition);
+                if let Some(message) = message {
+                    self.expression(message);
+                }
+            }
+            Stmt::Block { statements } => self.block(statements),
+            Stmt::Break { value, .. } => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                statics,
+            } => {
+                self.declare(name);
+                self.define(name);
+                if let Some(superclass) = superclass {
+                    self.expression(superclass);
+                }
+                for method in methods {
+                    self.function(method, true);
+                }
+                for field in statics {
+                    if let Stmt::Var {
+                        initializer: Some(initializer),
+                        ..
+                    } = field
+                    {
+                        self.expression(initializer);
+                    }
+                }
+            }
+            Stmt::Enum { name, .. } => {
+                self.declare(name);
+                self.define(name);
+            }
+            Stmt::Continue { .. } | Stmt::Fallthrough { .. } => {}
+            Stmt::Expression { expression }
+            | Stmt::Print { expression }
+            | Stmt::EPrint { expression } => self.expression(expression),
+            function @ Stmt::Function { name, .. } => {
+                // Defined before its body is resolved, such that the function can call itself.
+                self.declare(name);
+                self.define(name);
+                self.function(function, false);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Stmt::Return { keyword, value } => {
+                if !self.in_function {
+                    self.error(keyword, "Can't return from top-level code.");
+                }
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Stmt::Var { name, initializer } => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+                self.define(name);
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                else_branch,
+            } => {
+                self.expression(condition);
+                self.statement(body);
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                }
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Stmt::With {
+                name,
+                initializer,
+                body,
+                ..
+            } => {
+                self.expression(initializer);
+                self.scopes.push(HashMap::new());
+                self.declare(name);
+                self.define(name);
+                self.block(body);
+                self.scopes.pop();
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expr) {
+        match expression {
+            Expr::Literal { .. } => {}
+            Expr::Variable { name, depth } => {
+                let in_own_initializer = self
+                    .scopes
+                    .last()
+                    .and_then(|scope| scope.get(name.lexeme()))
+                    == Some(&false);
+                if in_own_initializer {
+                    self.error(name, "Can't read local variable in its own initializer.");
+                }
+                self.local(name, depth);
+            }
+            Expr::This { keyword, depth } => self.local(keyword, depth),
+            Expr::Assign { name, value, depth } => {
+                self.expression(value);
+                self.local(name, depth);
+            }
+            Expr::Logical { left, right, .. } | Expr::Binary { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expr::Unary { right, .. } => self.expression(right),
+            Expr::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                self.expression(then_branch);
+                self.expression(else_branch);
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+            Expr::Get { object, .. } => self.expression(object),
+            Expr::Set { object, value, .. } => {
+                self.expression(value);
+                self.expression(object);
+            }
+            Expr::List { elements } => {
+                for element in elements {
+                    self.expression(element);
+                }
+            }
+            Expr::Map { entries, .. } => {
+                for entry in entries {
+                    match entry {
+                        MapEntry::Pair(key, value) => {
+                            self.expression(key);
+                            self.expression(value);
+                        }
+                        MapEntry::Spread(spread) => self.expression(spread),
+                    }
+                }
+            }
+            Expr::Spread { expression, .. } => self.expression(expression),
+            Expr::Index { object, index, .. } => {
+                self.expression(object);
+                self.expression(index);
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+            }
+            Expr::Grouping { expression } => self.expression(expression),
+            Expr::Match {
+                subject,
+                arms,
+                wildcard,
+                ..
+            } => {
+                self.expression(subject);
+                for arm in arms {
+                    self.expression(&arm.body);
+                }
+                self.expression(wildcard);
+            }
+            Expr::Loop { body, .. } => self.block(body),
+            Expr::Switch {
+                subject,
+                cases,
+                default,
+                ..
+            } => {
+                self.expression(subject);
+                // Every case body is run as a block of its own.
+                for case in cases {
+                    self.expression(&case.value);
+                    self.block(&case.body);
+                }
+                self.block(default);
+            }
+        }
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        self.scopes.push(HashMap::new());
+        self.statements(statements);
+        self.scopes.pop();
+    }
+
+    /// Resolve the body of a function declaration. A method can refer to its instance as `this`,
+    /// which is defined along with the parameters.
+    fn function(&mut self, declaration: &Stmt, is_method: bool) {
+        let Stmt::Function { params, body, .. } = declaration else {
+            return;
+        };
+        let in_function = std::mem::replace(&mut self.in_function, true);
+
+        let mut scope = HashMap::new();
+        if is_method {
+            scope.insert("this".to_string(), true);
+        }
+        self.scopes.push(scope);
+        for param in params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.block(body);
+        self.scopes.pop();
+
+        self.in_function = in_function;
+    }
+
+    /// Declare `name` in the innermost scope, where it can't be read until it is defined.
+    fn declare(&mut self, name: &Token) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        if scope.insert(name.lexeme().to_string(), false).is_some() {
+            self.error(name, "Already a variable with this name in this scope.");
+        }
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme().to_string(), true);
+        }
+    }
+
+    /// Store in `depth` how many scopes out `name` is declared, leaving it `None` for a global.
+    fn local(&mut self, name: &Token, depth: &Depth) {
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(name.lexeme()));
+        depth.set(found);
+    }
+
+    fn error(&mut self, token: &Token, message: &str) {
+        self.errors
+            .push(LoxError::from_token(token, message.to_string()));
+    }
+}
@@ -1,33 +1,52 @@
-use crate::token::{Literal, Token, TokenType};
-use crate::LoxError;
+use crate::token::{Literal, Number, Token, TokenType};
+use crate::{LoxError, LoxErrors};
 
 pub(crate) struct Scanner<'s> {
     source: &'s str,
     tokens: Vec<Token>,
+    /// Byte offsets into the source, which always lie on a char boundary.
     start: usize,
     current: usize,
     /// 1-indexed line number.
     line: usize,
+    errors: LoxErrors,
 }
 
 impl<'s> Scanner<'s> {
-    pub(crate) fn new(source: &'s str) -> Self {
+    pub(crate) fn new(source: &'s str, max_errors: usize) -> Self {
         Self {
             source,
             tokens: Vec::default(),
             start: 0,
             current: 0,
             line: 1,
+            errors: LoxErrors::new(max_errors),
         }
     }
 
-    pub(crate) fn scan_tokens(mut self) -> Result<Vec<Token>, LoxError> {
+    /// Scan the complete source into tokens.
+    ///
+    /// Scanning continues past errors, such that all of them can be reported at once.
+    pub(crate) fn scan_tokens(mut self) -> Result<Vec<Token>, LoxErrors> {
         while !self.is_at_end() {
             self.start = self.current;
-            self.scan_token()?;
+            if let Err(error) = self.scan_token() {
+                self.errors.push(error)
+            }
+        }
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
         }
 
-        self.push_new_token_at_line(TokenType::Eof, "".to_string(), None, self.line, self.col());
+        self.start = self.current;
+        self.push_new_token_at_line(
+            TokenType::Eof,
+            "".to_string(),
+            None,
+            self.line,
+            self.start_col(),
+        );
         Ok(self.tokens)
     }
 
@@ -43,17 +62,35 @@ impl<'s> Scanner<'s> {
             ')' => self.push_token(RightParen),
             '{' => self.push_token(LeftBrace),
             '}' => self.push_token(RightBrace),
+            '[' => self.push_token(LeftBracket),
+            ']' => self.push_token(RightBracket),
+            ':' => self.push_token(Colon),
             ',' => self.push_token(Comma),
+            // A number may start with its decimal point, as in `.5`.
+            '.' if self.peek().is_some_and(|c| c.is_ascii_digit()) => self.number()?,
+            '.' if self.peek() == Some('.') && self.peek_next() == Some('.') => {
+                self.advance();
+                self.advance();
+                self.push_token(DotDotDot)
+            }
             '.' => self.push_token(Dot),
             '-' => self.push_token(Minus),
+            '%' => self.push_token(Percent),
             '+' => self.push_token(Plus),
+            '?' => self.push_token(Question),
             ';' => self.push_token(Semicolon),
-            '*' => self.push_token(Star),
+            '*' => self.push_token_if_match_next('*', StarStar, Star),
+            '&' => self.push_token(Ampersand),
+            '^' => self.push_token(Caret),
+            '|' => self.push_token(Pipe),
 
             // Two-character or single-character?
             '!' => self.push_token_if_match_next('=', BangEqual, Bang),
+            '=' if self.match_next('>') => self.push_token(FatArrow),
             '=' => self.push_token_if_match_next('=', EqualEqual, Equal),
+            '<' if self.match_next('<') => self.push_token(LessLess),
             '<' => self.push_token_if_match_next('=', LessEqual, Less),
+            '>' if self.match_next('>') => self.push_token(GreaterGreater),
             '>' => self.push_token_if_match_next('=', GreaterEqual, Greater),
 
             // Is it a comment or a slash...?
@@ -66,6 +103,8 @@ impl<'s> Scanner<'s> {
                     } {
                         self.advance();
                     }
+                } else if self.match_next('*') {
+                    self.block_comment()?
                 } else {
                     self.push_token(Slash)
                 }
@@ -83,7 +122,8 @@ impl<'s> Scanner<'s> {
             c if c.is_ascii_digit() => self.number()?,
 
             // Identifier literals.
-            c if c.is_ascii_alphabetic() => self.identifier()?,
+            c if c.is_alphabetic() || c == '_' => self.identifier()?,
+            '`' => self.escaped_identifier()?,
 
             // Anything else, we throw an error.
             _ => {
@@ -99,7 +139,8 @@ impl<'s> Scanner<'s> {
     }
 
     fn char_at(&self, index: usize) -> char {
-        self.source.as_bytes()[index] as char
+        // The index always lies on a char boundary, because the scanner moves by whole chars.
+        self.source[index..].chars().next().unwrap()
     }
 
     fn current_char(&self) -> char {
@@ -108,7 +149,7 @@ impl<'s> Scanner<'s> {
 
     pub(crate) fn advance(&mut self) -> char {
         let c = self.current_char();
-        self.current += 1;
+        self.current += c.len_utf8();
         c
     }
 
@@ -118,7 +159,7 @@ impl<'s> Scanner<'s> {
 
     fn push_new_token(&mut self, token_type: TokenType, literal: Option<Literal>) {
         let text = self.source[self.start..self.current].to_owned();
-        self.push_new_token_at_line(token_type, text, literal, self.line, self.col())
+        self.push_new_token_at_line(token_type, text, literal, self.line, self.start_col())
     }
 
     fn push_new_token_at_line(
@@ -133,12 +174,22 @@ impl<'s> Scanner<'s> {
             .push(Token::new(token_type, lexeme, literal, line, col))
     }
 
+    /// The 1-indexed column at which the current token starts.
+    fn start_col(&self) -> usize {
+        // Everything after the last newline before the token is on the same line as its start.
+        let before = self.source[..self.start]
+            .rsplit('\n')
+            .next()
+            .unwrap_or_default();
+        before.chars().count() + 1
+    }
+
     pub(crate) fn col(&self) -> usize {
         // TODO: I wonder whether this unwrap_or is actually ever hit. Is there an actual case
         // where None might occur? (curiosity bikeshed)
         match self.source[..self.current].lines().last() {
             None => 0,
-            Some(line) => line.len(),
+            Some(line) => line.chars().count(),
         }
     }
 
@@ -149,7 +200,7 @@ impl<'s> Scanner<'s> {
             return false;
         }
 
-        self.current += 1;
+        self.current += expected.len_utf8();
         true
     }
 
@@ -178,7 +229,7 @@ impl<'s> Scanner<'s> {
     }
 
     pub(crate) fn peek_next(&self) -> Option<char> {
-        let next_index = self.current + 1;
+        let next_index = self.current + self.peek()?.len_utf8();
         if next_index >= self.source.len() {
             return None;
         }
@@ -186,71 +237,272 @@ impl<'s> Scanner<'s> {
         Some(self.char_at(next_index))
     }
 
-    pub(crate) fn string(&mut self) -> Result<(), LoxError> {
-        // TODO: This is some terrible work. There must be a nice way to do this. Shame let
-        // chaining is not yet here...
-        while {
-            let c = self.peek();
-            c.is_some() && c.unwrap() != '"'
-        } {
-            if self.peek() == Some('\n') {
-                self.line += 1
+    /// Skip a block comment, which runs until the matching `*/`. Block comments nest, so commenting
+    /// out code that contains a block comment works. The opening `/*` has already been consumed.
+    pub(crate) fn block_comment(&mut self) -> Result<(), LoxError> {
+        // An unterminated comment is reported where it starts, rather than at the end of the file.
+        let (line, col) = (self.line, self.col());
+        let mut depth = 1;
+        loop {
+            match self.peek() {
+                None => {
+                    return Err(LoxError::new(
+                        line,
+                        col,
+                        "Unterminated block comment.".to_string(),
+                    ))
+                }
+                Some('/') if self.peek_next() == Some('*') => {
+                    self.advance();
+                    depth += 1;
+                }
+                Some('*') if self.peek_next() == Some('/') => {
+                    self.advance();
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance();
+                        return Ok(());
+                    }
+                }
+                Some('\n') => self.line += 1,
+                Some(_) => {}
             }
             self.advance();
         }
+    }
 
-        if self.is_at_end() {
-            // We have reached the end of the source code without termination of the string
-            // literal.
-            return Err(LoxError::new(
-                self.line,
-                self.col(),
-                "Unterminated string.".to_string(),
-            ));
+    pub(crate) fn string(&mut self) -> Result<(), LoxError> {
+        // The decoded value. Stretches without escape sequences are copied over from the source
+        // as a whole, starting at `run`.
+        let mut value = String::new();
+        let mut run = self.current;
+        loop {
+            match self.peek() {
+                // We have reached the end of the source code without termination of the string
+                // literal.
+                None => {
+                    return Err(LoxError::new(
+                        self.line,
+                        self.col(),
+                        "Unterminated string.".to_string(),
+                    ))
+                }
+                Some('"') => break,
+                Some('\\') => {
+                    value.push_str(&self.source[run..self.current]);
+                    self.advance();
+                    // An invalid escape sequence is reported, but the rest of the string is still
+                    // scanned as usual.
+                    match self.escape_sequence() {
+                        Ok(c) => value.push(c),
+                        Err(error) => self.errors.push(error),
+                    }
+                    run = self.current;
+                }
+                Some(c) => {
+                    if c == '\n' {
+                        self.line += 1
+                    }
+                    self.advance();
+                }
+            }
         }
+        value.push_str(&self.source[run..self.current]);
 
         // We advance for the closing ".
         self.advance();
-
-        // Trim the surrounding quotes.
-        let value = self.source[self.start + 1..self.current - 1].to_owned();
         self.push_new_token(TokenType::String, Some(Literal::String(value)));
 
         Ok(())
     }
 
-    pub(crate) fn number(&mut self) -> Result<(), LoxError> {
-        while {
-            let c = self.peek();
-            c.is_some() && c.unwrap().is_ascii_digit()
-        } {
+    /// Decode and consume the escape sequence after a backslash in a string literal.
+    fn escape_sequence(&mut self) -> Result<char, LoxError> {
+        let c = match self.source[self.current..].chars().next() {
+            Some(c) => c,
+            // Leave it to the string to report that it is unterminated.
+            None => return Ok('\\'),
+        };
+        self.current += c.len_utf8();
+        if c == '\n' {
+            self.line += 1
+        }
+
+        match c {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            '\\' => Ok('\\'),
+            '"' => Ok('"'),
+            '0' => Ok('\0'),
+            'u' => self.unicode_escape(),
+            c => Err(LoxError::new(
+                self.line,
+                self.col(),
+                format!("Invalid escape sequence '\\{c}'."),
+            )),
+        }
+    }
+
+    /// Decode the `{XXXX}` following a `\u` escape: a Unicode code point of one to six hex digits.
+    fn unicode_escape(&mut self) -> Result<char, LoxError> {
+        let error = |scanner: &Self, message: &str| {
+            Err(LoxError::new(
+                scanner.line,
+                scanner.col(),
+                message.to_string(),
+            ))
+        };
+
+        if !self.match_next('{') {
+            return error(self, "Expect '{' after '\\u'.");
+        }
+        let start = self.current;
+        while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
             self.advance();
         }
+        let digits = &self.source[start..self.current];
+        if digits.is_empty() || digits.len() > 6 {
+            return error(self, "Expect 1 to 6 hex digits in '\\u{...}'.");
+        }
+        if !self.match_next('}') {
+            return error(self, "Expect '}' after the hex digits of '\\u{...}'.");
+        }
+
+        // The digits have been checked, so this can only fail for the code point itself.
+        let code_point = u32::from_str_radix(digits, 16).unwrap();
+        match char::from_u32(code_point) {
+            Some(c) => Ok(c),
+            None => error(
+                self,
+                &format!("Invalid code point U+{code_point:X} in '\\u{{...}}'."),
+            ),
+        }
+    }
 
-        // Look for the fractional part.
-        if self.peek() == Some('.') && self.peek_next().unwrap_or(' ').is_ascii_digit() {
+    pub(crate) fn number(&mut self) -> Result<(), LoxError> {
+        if self.source[self.start..].starts_with('0') {
+            match self.peek() {
+                Some('b') => return self.radix_number(2, "binary"),
+                Some('o') => return self.radix_number(8, "octal"),
+                _ => {}
+            }
+        }
+
+        self.digits()?;
+
+        // Look for the fractional part, unless the number started with it.
+        let leading_dot = self.source[self.start..].starts_with('.');
+        if !leading_dot
+            && self.peek() == Some('.')
+            && self
+                .peek_next()
+                .is_some_and(|c| c.is_ascii_digit() || c == '_')
+        {
             // Consume the '.'.
             self.advance();
+            self.digits()?;
+        }
 
-            while {
-                let c = self.peek();
-                c.is_some() && c.unwrap().is_ascii_digit()
-            } {
+        // Look for the exponent.
+        if let Some('e' | 'E') = self.peek() {
+            self.advance();
+            if let Some('+' | '-') = self.peek() {
                 self.advance();
             }
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                return Err(LoxError::new(
+                    self.line,
+                    self.col(),
+                    "Expect digits in the exponent of a number.".to_string(),
+                ));
+            }
+            self.digits()?;
         }
 
-        // TODO: I actually don't think it is entirely safe to unwrap here... We'll see how it
-        // works in practice, and might later take a look at the possible failure modes.
-        let value = self.source[self.start..self.current].parse().unwrap();
+        // The scanned characters always form a valid number, so parsing can't fail.
+        let value = self.source[self.start..self.current]
+            .replace('_', "")
+            .parse()
+            .unwrap();
         self.push_new_token(TokenType::Number, Some(Literal::Number(value)));
         Ok(())
     }
 
+    /// Consume a run of decimal digits, which may be separated by single underscores for
+    /// readability, as in `1_000_000`.
+    fn digits(&mut self) -> Result<(), LoxError> {
+        // Any misplaced separator is reported once the whole run has been consumed.
+        let mut misplaced = None;
+        while let Some(c) = self.peek().filter(|&c| c.is_ascii_digit() || c == '_') {
+            let after_digit = self.source[..self.current]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_ascii_digit());
+            self.advance();
+            let before_digit = self.peek().is_some_and(|c| c.is_ascii_digit());
+            if c == '_' && !(after_digit && before_digit) && misplaced.is_none() {
+                misplaced = Some(self.col());
+            }
+        }
+
+        match misplaced {
+            Some(col) => Err(LoxError::new(
+                self.line,
+                col,
+                "A digit separator '_' must be between two digits.".to_string(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Scan an integer literal in the given `radix`, after its `0b` or `0o` prefix.
+    fn radix_number(&mut self, radix: u32, name: &str) -> Result<(), LoxError> {
+        // Consume the letter of the prefix.
+        self.advance();
+
+        // Any trailing alphanumeric characters are consumed as well, so that an invalid digit is
+        // reported rather than starting a new token.
+        let digits_start = self.current;
+        let mut invalid = None;
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_alphanumeric()) {
+            self.advance();
+            if invalid.is_none() && c.to_digit(radix).is_none() {
+                invalid = Some((c, self.col()));
+            }
+        }
+        if let Some((c, col)) = invalid {
+            return Err(LoxError::new(
+                self.line,
+                col,
+                format!("Invalid digit '{c}' in {name} literal."),
+            ));
+        }
+
+        let digits = &self.source[digits_start..self.current];
+        if digits.is_empty() {
+            return Err(LoxError::new(
+                self.line,
+                self.col(),
+                format!("Expect digits in {name} literal."),
+            ));
+        }
+        let value = i64::from_str_radix(digits, radix).map_err(|_| {
+            LoxError::new(
+                self.line,
+                self.col(),
+                format!("The {name} literal is too large."),
+            )
+        })?;
+
+        self.push_new_token(TokenType::Number, Some(Literal::Number(value as Number)));
+        Ok(())
+    }
+
     pub(crate) fn identifier(&mut self) -> Result<(), LoxError> {
         while {
             let c = self.peek();
-            c.is_some() && c.unwrap().is_ascii_alphanumeric()
+            c.is_some() && (c.unwrap().is_alphanumeric() || c.unwrap() == '_')
         } {
             self.advance();
         }
@@ -258,20 +510,35 @@ impl<'s> Scanner<'s> {
         use TokenType::*;
         let token_type = match &self.source[self.start..self.current] {
             "and" => And,
+            "assert" => Assert,
+            "break" => Break,
+            "case" => Case,
             "class" => Class,
+            "continue" => Continue,
+            "default" => Default,
             "else" => Else,
+            "enum" => Enum,
+            "eprint" => EPrint,
+            "fallthrough" => Fallthrough,
             "false" => False,
             "fun" => Fun,
             "for" => For,
             "if" => If,
+            "is" => Is,
+            "loop" => Loop,
+            "match" => Match,
             "nil" => Nil,
             "or" => Or,
             "print" => Print,
             "return" => Return,
+            "static" => Static,
+            "switch" => Switch,
             "this" => This,
             "true" => True,
+            "unless" => Unless,
             "var" => Var,
             "while" => While,
+            "with" => With,
             _ => Identifier,
         };
 
@@ -279,4 +546,89 @@ impl<'s> Scanner<'s> {
 
         Ok(())
     }
+
+    /// Scan an identifier written between backticks, like `` `for` `` or `` `my field` ``, which
+    /// may be a keyword or contain any character other than a newline. The lexeme is what is
+    /// between the backticks.
+    pub(crate) fn escaped_identifier(&mut self) -> Result<(), LoxError> {
+        while self.peek().is_some_and(|c| c != '`' && c != '\n') {
+            self.advance();
+        }
+        if self.peek() != Some('`') {
+            return Err(LoxError::new(
+                self.line,
+                self.col(),
+                "Unterminated identifier.".to_string(),
+            ));
+        }
+        self.advance();
+
+        let name = &self.source[self.start + 1..self.current - 1];
+        if name.is_empty() {
+            return Err(LoxError::new(
+                self.line,
+                self.col(),
+                "Expect an identifier between the backticks.".to_string(),
+            ));
+        }
+        self.push_new_token_at_line(
+            TokenType::Identifier,
+            name.to_string(),
+            None,
+            self.line,
+            self.start_col(),
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_start_at_their_column() {
+        let tokens = Scanner::new("var answer = 4 + 22;\n  print answer;", 1)
+            .scan_tokens()
+            .unwrap();
+        let columns = tokens
+            .iter()
+            .map(|token| (token.lexeme(), token.line(), token.col()))
+            .collect::<Vec<_>>();
+        let expected = [
+            ("var", 1, 1),
+            ("answer", 1, 5),
+            ("=", 1, 12),
+            ("4", 1, 14),
+            ("+", 1, 16),
+            ("22", 1, 18),
+            (";", 1, 20),
+            ("print", 2, 3),
+            ("answer", 2, 9),
+            (";", 2, 15),
+        ];
+        // The last token is the end of the input.
+        assert_eq!(columns[..columns.len() - 1], expected);
+
+        // Only tokens without whitespace between them follow each other.
+        assert!(tokens[6].follows(&tokens[5]));
+        assert!(!tokens[4].follows(&tokens[3]));
+        assert!(!tokens[7].follows(&tokens[6]));
+    }
+
+    #[test]
+    fn leading_underscore_starts_an_identifier() {
+        let tokens = Scanner::new("_1 _2nd 1_0", 1).scan_tokens().unwrap();
+        let scanned = tokens
+            .iter()
+            .map(|token| (token.token_type(), token.lexeme()))
+            .collect::<Vec<_>>();
+        let expected = [
+            (TokenType::Identifier, "_1"),
+            (TokenType::Identifier, "_2nd"),
+            (TokenType::Number, "1_0"),
+        ];
+        assert_eq!(scanned[..scanned.len() - 1], expected);
+    }
 }
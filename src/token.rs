@@ -1,9 +1,10 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
 use std::{fmt::Display, ops::Deref};
 
-use crate::{
-    callable::{Callable, Function},
-    environment::Environment,
-};
+use crate::callable::{Callable, Function, NativeFunction};
+use crate::class::{Class, Instance};
 
 #[derive(Debug, Clone)]
 pub struct Token {
@@ -11,6 +12,8 @@ pub struct Token {
     lexeme: String,
     literal: Option<Literal>,
     line: usize,
+    /// The 1-indexed column at which the lexeme starts. For a string spanning several lines, this
+    /// is a column on the line where it starts, even though `line` is the one where it ends.
     col: usize,
 }
 
@@ -50,6 +53,12 @@ impl Token {
     pub(crate) fn col(&self) -> usize {
         self.col
     }
+
+    /// Whether this token starts right where the `previous` token ends, without any whitespace in
+    /// between.
+    pub(crate) fn follows(&self, previous: &Token) -> bool {
+        self.line == previous.line && self.col == previous.col + previous.lexeme.chars().count()
+    }
 }
 
 impl Display for Token {
@@ -64,24 +73,29 @@ impl Display for Token {
     }
 }
 
+/// The type of Lox numbers. It is `f64`, unless the `f32` feature trades precision for size.
+#[cfg(not(feature = "f32"))]
+pub type Number = f64;
+#[cfg(feature = "f32")]
+pub type Number = f32;
+
 #[derive(Debug, Clone)]
 pub enum Literal {
-    Identifier(String),
     Fun(Box<Function>),
+    Native(NativeFunction),
+    Class(Rc<Class>),
+    /// Instances are shared, such that changes to a field are seen through every reference.
+    Instance(Rc<RefCell<Instance>>),
+    /// Like instances, lists are shared rather than copied.
+    List(Rc<RefCell<Vec<Literal>>>),
+    Map(Rc<RefCell<HashMap<String, Literal>>>),
     String(String),
-    Number(f64),
+    Number(Number),
     Nil,
     Bool(bool),
 }
 
 impl Literal {
-    fn identifier(&self) -> Option<&String> {
-        match self {
-            Literal::Identifier(s) => Some(s),
-            _ => None,
-        }
-    }
-
     pub(crate) fn string(&self) -> Option<&String> {
         match self {
             Literal::String(s) => Some(s),
@@ -89,13 +103,20 @@ impl Literal {
         }
     }
 
-    pub(crate) fn number(&self) -> Option<f64> {
+    pub(crate) fn number(&self) -> Option<Number> {
         match self {
             Literal::Number(n) => Some(*n),
             _ => None,
         }
     }
 
+    /// The number as an integer, if it is a whole number that fits in an `i64`.
+    pub(crate) fn integer(&self) -> Option<i64> {
+        self.number()
+            .filter(|n| n.fract() == 0.0 && (i64::MIN as Number..i64::MAX as Number).contains(n))
+            .map(|n| n as i64)
+    }
+
     pub(crate) fn bool(&self) -> Option<bool> {
         match self {
             Literal::Bool(b) => Some(*b),
@@ -119,10 +140,25 @@ impl Literal {
         }
     }
 
+    /// Lists and maps are equal when their contents are. Other values that are shared, like
+    /// instances, are only equal to themselves.
     pub(crate) fn is_equal(left: Literal, right: Literal) -> Self {
+        let equal = |a: &Literal, b: &Literal| Literal::is_equal(a.clone(), b.clone()).is_truthy();
         let equality = match (left, right) {
-            (Literal::Identifier(a), Literal::Identifier(b)) => a == b,
             (Literal::Fun(a), Literal::Fun(b)) => a.name().lexeme() == b.name().lexeme(),
+            (Literal::Native(a), Literal::Native(b)) => a.name() == b.name(),
+            (Literal::Class(a), Literal::Class(b)) => Rc::ptr_eq(&a, &b),
+            (Literal::Instance(a), Literal::Instance(b)) => Rc::ptr_eq(&a, &b),
+            (Literal::List(a), Literal::List(b)) => {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| equal(a, b))
+            }
+            (Literal::Map(a), Literal::Map(b)) => {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len()
+                    && a.iter()
+                        .all(|(key, a)| b.get(key).is_some_and(|b| equal(a, b)))
+            }
             (Literal::String(a), Literal::String(b)) => a == b,
             (Literal::Number(a), Literal::Number(b)) => a == b,
             (Literal::Nil, Literal::Nil) => true,
@@ -133,11 +169,21 @@ impl Literal {
         Self::Bool(equality)
     }
 
+    /// Whether `left` and `right` are the same list, map, instance or class, rather than ones with
+    /// the same contents. Other values are compared like `==` does.
+    pub(crate) fn is_identical(left: &Literal, right: &Literal) -> bool {
+        match (left, right) {
+            (Literal::List(a), Literal::List(b)) => Rc::ptr_eq(a, b),
+            (Literal::Map(a), Literal::Map(b)) => Rc::ptr_eq(a, b),
+            _ => Literal::is_equal(left.clone(), right.clone()).is_truthy(),
+        }
+    }
+
     pub(crate) fn operate_string(&self, f: impl Fn(String) -> String) -> Option<Self> {
         self.string().map(|s| Self::String(f(s.clone())))
     }
 
-    pub(crate) fn operate_number(&self, f: impl Fn(f64) -> f64) -> Option<Self> {
+    pub(crate) fn operate_number(&self, f: impl Fn(Number) -> Number) -> Option<Self> {
         self.number().map(|n| Self::Number(f(n)))
     }
 
@@ -152,16 +198,37 @@ impl Literal {
     pub(crate) fn operate_number_binary(
         &self,
         right: Self,
-        f: impl Fn(f64, f64) -> f64,
+        f: impl Fn(Number, Number) -> Number,
     ) -> Option<Self> {
         let left = self;
         let right = right.number()?;
         left.operate_number(|n| f(n, right))
     }
 
-    pub(crate) fn callable(&self) -> Option<impl Callable> {
+    pub(crate) fn compare_numbers(
+        &self,
+        right: Self,
+        compare: impl Fn(&Number, &Number) -> bool,
+    ) -> Option<Self> {
+        Some(Self::Bool(compare(&self.number()?, &right.number()?)))
+    }
+
+    /// Apply `f` to both numbers as integers, which they must be whole numbers for.
+    pub(crate) fn operate_integer_binary(
+        &self,
+        right: Self,
+        f: impl Fn(i64, i64) -> i64,
+    ) -> Option<Self> {
+        let left = self.integer()?;
+        let right = right.integer()?;
+        Some(Self::Number(f(left, right) as Number))
+    }
+
+    pub(crate) fn callable(&self) -> Option<&dyn Callable> {
         match self {
-            Self::Fun(fun) => Some(*fun.clone()),
+            Self::Fun(fun) => Some(fun.as_ref()),
+            Self::Native(native) => Some(native),
+            Self::Class(class) => Some(class),
             _ => None,
         }
     }
@@ -170,11 +237,40 @@ impl Literal {
 impl Display for Literal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Literal::Identifier(i) => write!(f, "<{i}>"),
             Literal::Fun(fun) => {
                 let name = fun.deref().name().lexeme();
                 write!(f, "<fn {name}>")
             }
+            Literal::Native(native) => write!(f, "<native fn {}>", native.name()),
+            Literal::Class(class) => write!(f, "<class {}>", class.name().lexeme()),
+            Literal::List(elements) => {
+                let elements = elements
+                    .borrow()
+                    .iter()
+                    .map(|element| element.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "[{elements}]")
+            }
+            Literal::Map(entries) => {
+                let entries = entries.borrow();
+                // Sorted, such that printing a map always gives the same result.
+                let mut keys = entries.keys().collect::<Vec<_>>();
+                keys.sort();
+                let entries = keys
+                    .into_iter()
+                    .map(|key| format!("{key}: {}", entries[key]))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "{{{entries}}}")
+            }
+            Literal::Instance(instance) => {
+                write!(
+                    f,
+                    "<{} instance>",
+                    instance.borrow().class().name().lexeme()
+                )
+            }
             Literal::String(s) => write!(f, "{s}"),
             Literal::Number(n) => write!(f, "{n}"),
             Literal::Nil => write!(f, "nil"),
@@ -190,23 +286,38 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Dot,
+    Colon,
     Comma,
     Minus,
+    Percent,
     Plus,
+    Question,
     Semicolon,
     Slash,
     Star,
+    Ampersand,
+    Caret,
+    Pipe,
 
     // One or two character tokens.
     Bang,
     BangEqual,
     Equal,
     EqualEqual,
+    FatArrow,
     Greater,
     GreaterEqual,
+    GreaterGreater,
     Less,
     LessEqual,
+    LessLess,
+    StarStar,
+
+    // Three character tokens.
+    DotDotDot,
 
     // Literals.
     Identifier,
@@ -215,20 +326,73 @@ pub enum TokenType {
 
     // Keywords.
     And,
+    Assert,
+    Break,
+    Case,
     Class,
+    Continue,
+    Default,
     Else,
+    Enum,
+    EPrint,
+    Fallthrough,
     False,
     Fun,
     For,
     If,
+    Is,
+    Loop,
+    Match,
     Nil,
     Or,
     Print,
     Return,
+    Static,
+    Switch,
     This,
     True,
+    Unless,
     Var,
     While,
+    With,
 
     Eof,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    /// Scan `source` as a single number literal, and print it like Lox does.
+    fn scanned(source: &str) -> String {
+        let tokens = Scanner::new(source, 1).scan_tokens().unwrap();
+        tokens[0].literal().unwrap().to_string()
+    }
+
+    /// Add two numbers and print the sum like Lox does.
+    fn sum(a: Number, b: Number) -> String {
+        Literal::Number(a)
+            .operate_number_binary(Literal::Number(b), |a, b| a + b)
+            .unwrap()
+            .to_string()
+    }
+
+    #[cfg(not(feature = "f32"))]
+    #[test]
+    fn numbers_are_double_precision() {
+        // 2^24 + 1 and 2^53 are exact, and 2^53 + 1 is the first integer that is not.
+        assert_eq!(scanned("16777217"), "16777217");
+        assert_eq!(scanned("9007199254740993"), "9007199254740992");
+        assert_eq!(sum(0.1, 0.2), "0.30000000000000004");
+    }
+
+    #[cfg(feature = "f32")]
+    #[test]
+    fn numbers_are_single_precision() {
+        // 2^24 + 1 is the first integer that is not exact.
+        assert_eq!(scanned("16777216"), "16777216");
+        assert_eq!(scanned("16777217"), "16777216");
+        assert_eq!(sum(0.1, 0.2), "0.3");
+    }
+}
@@ -0,0 +1,268 @@
+//! Runs the interpreter on the scripts in `tests/scripts`, to check what a script can't check
+//! itself: how it fails, and what it writes to stderr. Also runs the example scripts in the root of
+//! the repository, which check themselves with `assert`.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Run `rlox` with the `args` and the environment variables `vars`, feeding it `input` on stdin.
+fn rlox(args: &[&str], vars: &[(&str, &str)], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .envs(vars.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start rlox");
+    // rlox may exit before reading all of its input, which is fine.
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    child.wait_with_output().unwrap()
+}
+
+/// The path of the script `name` in `tests/scripts`.
+fn script(name: &str) -> String {
+    format!("{}/tests/scripts/{name}", env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Run the script `name` from `tests/scripts`, with the `options` before the `run` command.
+fn run_script(options: &[&str], name: &str) -> Output {
+    let path = script(name);
+    rlox(&[options, &["run", &path]].concat(), &[], "")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// The first line of every error in the output, leaving out the source excerpts.
+fn errors(output: &Output) -> Vec<String> {
+    stderr(output)
+        .lines()
+        .filter(|line| line.starts_with('['))
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn failing_assert_reports_its_condition() {
+    let output = run_script(&[], "assert_failure.lox");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(
+        stderr(&output),
+        "[line 4, col 1] Error at 'assert': Assertion failed: x > 10\n\
+         \x20   assert x > 10;\n\
+         \x20   ^\n"
+    );
+}
+
+#[test]
+fn errors_beyond_max_errors_are_counted() {
+    let output = run_script(&["--max-errors", "2"], "many_errors.lox");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(
+        stderr(&output),
+        "[line 2, col 5] Error at '1': Expect variable name.\n\
+         \x20   var 1;\n\
+         \x20       ^\n\
+         [line 3, col 5] Error at '2': Expect variable name.\n\
+         \x20   var 2;\n\
+         \x20       ^\n\
+         ... and 2 more errors.\n"
+    );
+
+    let output = run_script(&["--max-errors", "3"], "many_errors.lox");
+    assert!(stderr(&output).ends_with("^\n... and 1 more error.\n"));
+
+    let output = run_script(&[], "many_errors.lox");
+    assert_eq!(stderr(&output).matches("Expect variable name.").count(), 4);
+}
+
+#[test]
+fn eprint_writes_to_stderr() {
+    let output = run_script(&[], "eprint.lox");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "to stdout\n");
+    assert_eq!(stderr(&output), "to stderr\n3\n");
+}
+
+#[test]
+fn getenv_is_unavailable_in_the_sandbox() {
+    let path = script("getenv.lox");
+    let vars = [("RLOX_GREETING", "hello")];
+
+    let output = rlox(&["run", &path], &vars, "");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "hello\nnil\n");
+
+    let output = rlox(&["--sandbox", "run", &path], &vars, "");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).contains("Can't use 'getenv' in sandbox mode."));
+}
+
+#[test]
+fn asi_ends_statements_at_newlines() {
+    let output = run_script(&["--asi"], "asi.lox");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "3\nin a block\n4\ntwo on one line\n");
+
+    // Without --asi, the missing ';' are errors.
+    let output = run_script(&[], "asi.lox");
+    assert_eq!(output.status.code(), Some(65));
+    assert!(stderr(&output)
+        .starts_with("[line 3, col 1] Error at 'var': Expect ';' after variable declaration."));
+}
+
+#[test]
+fn asi_recovers_from_an_error_at_the_next_line() {
+    let output = run_script(&["--asi"], "asi_errors.lox");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(
+        errors(&output),
+        [
+            "[line 1, col 5] Error at '1': Expect variable name.",
+            "[line 3, col 7] Error at ')': Expect expression.",
+        ]
+    );
+}
+
+#[test]
+fn calls_outside_the_arity_range_are_errors() {
+    // parseInt takes a string and an optional base.
+    let input = "parseInt();\nparseInt(\"7\", 10, 1);\nprint parseInt(\"7\");\n";
+    let output = rlox(&["--prompt", ""], &[], input);
+    assert_eq!(stdout(&output), "7\n");
+    let errors = stderr(&output);
+    assert!(errors.contains("Error at ')': Expected 1 to 2 arguments but got 0."));
+    assert!(errors.contains("Error at ')': Expected 1 to 2 arguments but got 3."));
+}
+
+#[test]
+fn errors_in_multiline_expressions_are_reported_at_the_operator() {
+    let output = run_script(&[], "multiline_error.lox");
+    assert_eq!(output.status.code(), Some(65));
+    assert!(stderr(&output).starts_with("[line 2, col 3] Error at '+':"));
+}
+
+#[test]
+fn implicit_globals_only_with_the_flag() {
+    let output = run_script(&["--implicit-globals"], "implicit_globals.lox");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "1\n");
+
+    let output = run_script(&[], "implicit_globals.lox");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output)
+        .starts_with("[line 4, col 5] Error at 'count': Undefined variable 'count'."));
+}
+
+#[test]
+fn ast_json_matches_the_golden_file() {
+    let output = rlox(&["ast", "--json", &script("print_sum.lox")], &[], "");
+    assert!(output.status.success());
+    let golden = std::fs::read_to_string(script("print_sum.json")).unwrap();
+    assert_eq!(stdout(&output), golden);
+}
+
+#[test]
+fn asi_reports_errors_on_consecutive_lines() {
+    let output = run_script(&["--asi"], "asi_error_lines.lox");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        errors(&output),
+        [
+            "[line 1, col 5] Error at '1': Expect variable name.",
+            "[line 2, col 5] Error at ')': Expect expression.",
+            // The '(' on line 4 is left open, so the expression continues on line 5.
+            "[line 5, col 1] Error at 'var': Expect expression.",
+            "[line 5, col 5] Error at '5': Expect variable name.",
+        ]
+    );
+
+    // Without --asi, recovery skips to the next statement keyword, passing over lines 2 to 4.
+    let output = run_script(&[], "asi_error_lines.lox");
+    assert_eq!(errors(&output).len(), 2);
+}
+
+#[test]
+fn if_directives_keep_lines_only_for_defined_flags() {
+    let output = run_script(&[], "flags.lox");
+    assert_eq!(stdout(&output), "always\nend\n");
+
+    let output = run_script(&["--define", "DEBUG"], "flags.lox");
+    assert_eq!(stdout(&output), "always\ndebug\nend\n");
+
+    // A nested block is only kept if the flags of all enclosing blocks are defined too.
+    let output = run_script(&["--define", "VERBOSE"], "flags.lox");
+    assert_eq!(stdout(&output), "always\nend\n");
+
+    let output = run_script(&["--define", "DEBUG", "--define", "VERBOSE"], "flags.lox");
+    assert_eq!(stdout(&output), "always\ndebug\nverbose\nend\n");
+}
+
+#[test]
+fn errors_report_the_column_of_their_token() {
+    let output = run_script(&[], "column.lox");
+    assert_eq!(
+        stderr(&output),
+        "[line 1, col 22] Error at ')': Expect expression.\n\
+         \x20   var total = 4 + (2 * );\n\
+         \x20                        ^\n"
+    );
+}
+
+#[test]
+fn example_scripts_run_without_errors() {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let mut scripts: Vec<_> = std::fs::read_dir(root)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "lox"))
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+
+    for path in scripts {
+        // `input.lox` reads lines until its input runs out.
+        let output = rlox(&["run", path.to_str().unwrap()], &[], "one\ntwo\n\nthree\n");
+        assert!(
+            output.status.success(),
+            "{} failed:\n{}",
+            path.display(),
+            stderr(&output)
+        );
+    }
+}
+
+#[test]
+fn value_lists_copies_lists_when_assigned_or_passed() {
+    let output = run_script(&[], "value_lists.lox");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "[10, [20]]\n[changed]\n");
+
+    let output = run_script(&["--value-lists"], "value_lists.lox");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "[1, [2]]\n[original]\n");
+}
+
+#[test]
+fn alloc_stats_counts_the_values_a_script_allocates() {
+    let output = run_script(&["--alloc-stats"], "alloc_stats.lox");
+    assert!(output.status.success());
+    assert_eq!(
+        stderr(&output),
+        "Allocated lists: 7, maps: 2, instances: 2, functions: 2\n"
+    );
+
+    let output = run_script(&[], "alloc_stats.lox");
+    assert_eq!(stderr(&output), "");
+}